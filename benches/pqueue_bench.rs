@@ -2,11 +2,15 @@ use std::hint::black_box;
 use std::num::NonZeroUsize;
 use std::time::Duration;
 use criterion::{criterion_group, criterion_main, Criterion};
-use test_pqueue::queue::{Neighbor, Queue};
+use test_pqueue::nearest::NearestQueue;
+use test_pqueue::packed::PackedQueue;
+use test_pqueue::queue::{merge_k, neighbor_cmp, Neighbor, Queue};
+use test_pqueue::testing::gen_neighbors;
 
 
+// run it once as is and once with `--features cmov-compare` to compare both comparators
 fn bench_pqueue_insert( c: &mut Criterion ) {
-  let neighbors = generate_random_neighbors();
+  let neighbors = gen_neighbors( 100, [ 42u8; 32 ] );
   let mut group = c.benchmark_group( "pqueue-insert" );
   group.measurement_time( Duration::from_secs(5) ).sample_size( 10_000 );
 
@@ -22,30 +26,232 @@ fn bench_pqueue_insert( c: &mut Criterion ) {
   });
 }
 
-fn generate_random_neighbors() -> Vec<Neighbor> {
-  use rand::{
-    SeedableRng,
-    distr::{Distribution, Uniform},
-    rngs::StdRng,
-    seq::SliceRandom,
-  };
+fn bench_pqueue_insert_single( c: &mut Criterion ) {
+  let neighbors = gen_neighbors( 100, [ 42u8; 32 ] );
+  let mut group = c.benchmark_group( "pqueue-insert-single" );
+  group.measurement_time( Duration::from_secs(5) ).sample_size( 10_000 );
+
+  let mut queue = Queue::with_capacity( NonZeroUsize::MIN );
+  group.bench_function( "Priority Queue Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        queue.insert(black_box( *neighbor ));
+      }
+      black_box( &queue );
+    });
+  });
+
+  let mut queue = NearestQueue::new();
+  group.bench_function( "Nearest Queue Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        queue.insert(black_box( *neighbor ));
+      }
+      black_box( &queue );
+    });
+  });
+}
+
+fn bench_pqueue_insert_hinted( c: &mut Criterion ) {
+  let neighbors = generate_local_neighbors();
+  let mut group = c.benchmark_group( "pqueue-insert-hinted" );
+  group.measurement_time( Duration::from_secs(5) ).sample_size( 10_000 );
+
+  let mut queue = Queue::with_capacity( NonZeroUsize::new(64).unwrap() );
+  group.bench_function( "Priority Queue Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        queue.insert(black_box( *neighbor ));
+      }
+      black_box( &queue );
+    });
+  });
+
+  group.bench_function( "Priority Queue Insert Hinted", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      let mut hint = 0;
+      for neighbor in neighbors.iter() {
+        hint = queue.insert_hinted( black_box( *neighbor ), hint );
+      }
+      black_box( &queue );
+    });
+  });
+}
+
+fn bench_pqueue_insert_id_dist( c: &mut Criterion ) {
+  let neighbors = gen_neighbors( 100, [ 42u8; 32 ] );
+  let mut group = c.benchmark_group( "pqueue-insert-id-dist" );
+  group.measurement_time( Duration::from_secs(5) ).sample_size( 10_000 );
+
+  let mut queue = Queue::with_capacity( NonZeroUsize::new(64).unwrap() );
+  group.bench_function( "Priority Queue Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        let (id, dist) = black_box( (neighbor.id, neighbor.dist) );
+        queue.insert( Neighbor::new( id, dist ) );
+      }
+      black_box( &queue );
+    });
+  });
+
+  group.bench_function( "Priority Queue Insert Id Dist", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        let (id, dist) = black_box( (neighbor.id, neighbor.dist) );
+        queue.insert_id_dist( id, dist );
+      }
+      black_box( &queue );
+    });
+  });
+}
+
+fn bench_pqueue_try_reject( c: &mut Criterion ) {
+  let neighbors = gen_neighbors( 100, [ 42u8; 32 ] );
+  let mut group = c.benchmark_group( "pqueue-try-reject" );
+  group.measurement_time( Duration::from_secs(5) ).sample_size( 10_000 );
+
+  // a small capacity so that most of the inserts are rejected
+  let mut queue = Queue::with_capacity( NonZeroUsize::new(8).unwrap() );
+  group.bench_function( "Priority Queue Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        queue.insert(black_box( *neighbor ));
+      }
+      black_box( &queue );
+    });
+  });
+
+  group.bench_function( "Priority Queue Try Reject Then Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        let neighbor = black_box( *neighbor );
+        if queue.try_reject( neighbor.dist ) { continue }
+        queue.insert( neighbor );
+      }
+      black_box( &queue );
+    });
+  });
+}
+
+fn bench_pqueue_packed( c: &mut Criterion ) {
+  let neighbors = gen_neighbors( 1_000, [ 42u8; 32 ] );
+  let mut group = c.benchmark_group( "pqueue-packed" );
+  group.measurement_time( Duration::from_secs(5) ).sample_size( 10_000 );
+
+  let capacity = NonZeroUsize::new(256).unwrap();
+  let mut queue = Queue::with_capacity( capacity );
+  group.bench_function( "Priority Queue Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        queue.insert(black_box( *neighbor ));
+      }
+      black_box( &queue );
+    });
+  });
+
+  let mut queue = PackedQueue::with_capacity( capacity );
+  group.bench_function( "Packed Priority Queue Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        queue.insert(black_box( *neighbor ));
+      }
+      black_box( &queue );
+    });
+  });
+}
 
-  let seed = [ 42u8; 32 ];
-  let mut rng = StdRng::from_seed( seed );
-  let range = Uniform::new( 0.0f32, 1.0f32 ).unwrap();
+fn bench_pqueue_sorted_run( c: &mut Criterion ) {
+  let mut neighbors = gen_neighbors( 1_000, [ 42u8; 32 ] );
+  neighbors.sort_by( neighbor_cmp );
+  let mut group = c.benchmark_group( "pqueue-sorted-run" );
+  group.measurement_time( Duration::from_secs(5) ).sample_size( 10_000 );
 
-  let mut neighbors = Vec::with_capacity( 100 );
-  let mut ids = (0..100).collect::<Vec<u32>>();
-  ids.shuffle( &mut rng );
+  let mut queue = Queue::with_capacity( NonZeroUsize::new(64).unwrap() );
+  group.bench_function( "Priority Queue Insert", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for neighbor in neighbors.iter() {
+        queue.insert(black_box( *neighbor ));
+      }
+      black_box( &queue );
+    });
+  });
 
-  for id in ids {
-      let dist = range.sample( &mut rng );
-      let neighbor = Neighbor{ id, dist };
-      neighbors.push( neighbor );
-  }
+  group.bench_function( "Priority Queue Insert Sorted Run", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      queue.insert_sorted_run( neighbors.iter().map( |neighbor| black_box( *neighbor ) ) );
+      black_box( &queue );
+    });
+  });
+}
+
+fn bench_pqueue_merge( c: &mut Criterion ) {
+  let capacity = NonZeroUsize::new(64).unwrap();
+  let shards = generate_shards( capacity );
+  let mut group = c.benchmark_group( "pqueue-merge" );
+  group.measurement_time( Duration::from_secs(5) ).sample_size( 10_000 );
+
+  group.bench_function( "Priority Queue Merge K-Way", |bencher| {
+    bencher.iter( || {
+      black_box( merge_k( black_box( &shards ), capacity ) );
+    });
+  });
+
+  let mut queue = Queue::with_capacity( capacity );
+  group.bench_function( "Priority Queue Merge Pairwise", |bencher| {
+    bencher.iter( || {
+      queue.clear();
+      for shard in black_box( &shards ) {
+        for neighbor in shard.as_slice() {
+          queue.insert( *neighbor );
+        }
+      }
+      black_box( &queue );
+    });
+  });
+}
+
+// random walk over the distances, so consecutive neighbors land close to each other in the queue: the uniform distances of
+// `gen_neighbors` become steps in [-0.01, 0.01)
+fn generate_local_neighbors() -> Vec<Neighbor> {
+  let mut dist = 0.5f32;
+  gen_neighbors( 1_000, [ 42u8; 32 ] ).into_iter().map( |neighbor| {
+    dist = (dist + (neighbor.dist - 0.5) * 0.02).clamp( 0.0, 1.0 );
+    Neighbor::new( neighbor.id, dist )
+  }).collect()
+}
 
-  neighbors
+// 8 shards of 100 neighbors with distinct ids
+fn generate_shards( capacity: NonZeroUsize ) -> Vec<Queue> {
+  gen_neighbors( 800, [ 42u8; 32 ] ).chunks( 100 ).map( |shard| {
+    let mut queue = Queue::with_capacity( capacity );
+    for neighbor in shard {
+      queue.insert( *neighbor );
+    }
+    queue
+  }).collect()
 }
 
-criterion_group!( benches, bench_pqueue_insert );
+criterion_group!(
+  benches,
+  bench_pqueue_insert,
+  bench_pqueue_insert_single,
+  bench_pqueue_insert_hinted,
+  bench_pqueue_insert_id_dist,
+  bench_pqueue_try_reject,
+  bench_pqueue_packed,
+  bench_pqueue_sorted_run,
+  bench_pqueue_merge,
+);
 criterion_main!( benches );
@@ -1,10 +1,11 @@
 use std::hint::black_box;
 use std::num::NonZeroUsize;
 use std::time::Instant;
-use test_pqueue::queue::{Neighbor, Queue};
+use test_pqueue::queue::Queue;
+use test_pqueue::testing::gen_neighbors;
 
 pub fn main() {
-  let data = generate_random_floats();
+  let data = gen_neighbors( 100, [ 42u8; 32 ] );
   let mut queue = Queue::with_capacity( NonZeroUsize::new(64).unwrap() );
 
   let start = Instant::now();
@@ -17,28 +18,3 @@ pub fn main() {
   }
   println!( "done in {elapsed}ms", elapsed = start.elapsed().as_millis() );
 }
-
-fn generate_random_floats() -> Vec<Neighbor> {
-  use rand::{
-    SeedableRng,
-    distr::{Distribution, Uniform},
-    rngs::StdRng,
-    seq::SliceRandom,
-  };
-
-  let seed = [ 42u8; 32 ];
-  let mut rng = StdRng::from_seed( seed );
-  let range = Uniform::new( 0.0f32, 1.0f32 ).unwrap();
-
-  let mut neighbors = Vec::with_capacity( 100 );
-  let mut ids = (0..100).collect::<Vec<u32>>();
-  ids.shuffle( &mut rng );
-
-  for id in ids {
-      let dist = range.sample( &mut rng );
-      let neighbor = Neighbor{ id, dist };
-      neighbors.push( neighbor );
-  }
-
-  neighbors
-}
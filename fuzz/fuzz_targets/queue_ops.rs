@@ -0,0 +1,76 @@
+#![no_main]
+
+use std::num::NonZeroUsize;
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use test_pqueue::queue::{merge_k, Neighbor, Queue};
+use test_pqueue::reference::{assert_eq_reference, ReferenceQueue};
+
+// distances are drawn from a small grid so that ties on `dist` (and exact duplicates) are frequent
+#[derive(Debug, Arbitrary)]
+struct Input {
+  capacity: u8,
+  ops: Vec<Op>,
+}
+
+#[derive(Debug, Arbitrary)]
+enum Op {
+  Insert { id: u8, dist: u8 },
+  InsertHinted { id: u8, dist: u8, hint: u8 },
+  MergeK { shards: Vec<Vec<(u8, u8)>> },
+  SetCapacity { capacity: u8 },
+  Clear,
+}
+
+fn neighbor( id: u8, dist: u8 ) -> Neighbor {
+  Neighbor::new( id as u32, dist as f32 / 16.0 )
+}
+
+fn check( queue: &Queue, reference: &ReferenceQueue, capacity: NonZeroUsize ) {
+  let neighbors = queue.as_slice();
+  assert!( neighbors.len() <= capacity.get() );
+  assert!( neighbors.windows( 2 ).all( |w| (w[0].dist, w[0].id) < (w[1].dist, w[1].id) ) );
+  assert_eq_reference( queue, reference );
+}
+
+fuzz_target!( |input: Input| {
+  let Some( mut capacity ) = NonZeroUsize::new( input.capacity as usize ) else { return };
+  let mut queue = Queue::with_capacity( capacity );
+  let mut reference = ReferenceQueue::with_capacity( capacity );
+
+  for op in input.ops {
+    match op {
+      Op::Insert { id, dist } => {
+        queue.insert( neighbor( id, dist ) );
+        reference.insert( neighbor( id, dist ) );
+      }
+      Op::InsertHinted { id, dist, hint } => {
+        _ = queue.insert_hinted( neighbor( id, dist ), hint as usize );
+        reference.insert( neighbor( id, dist ) );
+      }
+      Op::MergeK { shards } => {
+        let mut queues = vec![ std::mem::replace( &mut queue, Queue::with_capacity( capacity ) ) ];
+        for shard in shards {
+          let mut shard_queue = Queue::with_capacity( capacity );
+          for (id, dist) in shard {
+            shard_queue.insert( neighbor( id, dist ) );
+            reference.insert( neighbor( id, dist ) );
+          }
+          queues.push( shard_queue );
+        }
+        queue = merge_k( &queues, capacity );
+      }
+      Op::SetCapacity { capacity: new_capacity } => {
+        let Some( new_capacity ) = NonZeroUsize::new( new_capacity as usize ) else { continue };
+        capacity = new_capacity;
+        queue.set_capacity( capacity );
+        reference.set_capacity( capacity );
+      }
+      Op::Clear => {
+        queue.clear();
+        reference.clear();
+      }
+    }
+    check( &queue, &reference, capacity );
+  }
+});
@@ -0,0 +1,123 @@
+#[cfg(feature = "allocator-api2")]
+use std::{alloc::Layout, ptr::NonNull};
+#[cfg(feature = "allocator-api2")]
+use allocator_api2::alloc::AllocError;
+use crate::queue::Neighbor;
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// the allocator of a `Queue` buffer. With the `allocator-api2` feature it is any `allocator_api2` allocator and the buffer
+// is an `allocator_api2::vec::Vec`, without it `Global` is the only allocator and the buffer is a plain `Vec`.
+
+#[cfg(feature = "allocator-api2")]
+pub use allocator_api2::alloc::{Allocator, Global};
+
+#[cfg(feature = "allocator-api2")]
+pub(crate) type Buffer<A> = allocator_api2::vec::Vec<Neighbor, A>;
+
+#[cfg(not(feature = "allocator-api2"))]
+pub use std_alloc::{Allocator, Global};
+
+#[cfg(not(feature = "allocator-api2"))]
+pub(crate) use std_alloc::Buffer;
+
+// `Global` buffers convert from and to `Vec` without a copy
+#[cfg(feature = "allocator-api2")]
+pub(crate) fn into_vec( buffer: Buffer<Global> ) -> Vec<Neighbor> {
+  let (ptr, len, capacity) = buffer.into_raw_parts();
+  // `allocator_api2::alloc::Global` forwards to the global allocator, the one of `Vec`
+  unsafe { Vec::from_raw_parts( ptr, len, capacity ) }
+}
+
+#[cfg(feature = "allocator-api2")]
+pub(crate) fn from_vec( vec: Vec<Neighbor> ) -> Buffer<Global> {
+  let mut vec = std::mem::ManuallyDrop::new( vec );
+  let (ptr, len, capacity) = (vec.as_mut_ptr(), vec.len(), vec.capacity());
+  unsafe { Buffer::from_raw_parts( ptr, len, capacity ) }
+}
+
+#[cfg(not(feature = "allocator-api2"))]
+pub(crate) fn into_vec( buffer: Buffer<Global> ) -> Vec<Neighbor> {
+  buffer.vec
+}
+
+#[cfg(not(feature = "allocator-api2"))]
+pub(crate) fn from_vec( vec: Vec<Neighbor> ) -> Buffer<Global> {
+  Buffer { vec, alloc: Global }
+}
+
+// allocator adapter aligning every allocation of `A` to 32 bytes, e.g. for AVX loads over the buffer of a
+// `Queue::with_capacity_power_of_two_in`
+#[cfg(feature = "allocator-api2")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Aligned32<A = Global>( pub A );
+
+#[cfg(feature = "allocator-api2")]
+unsafe impl<A: Allocator> Allocator for Aligned32<A> {
+  fn allocate( &self, layout: Layout ) -> Result<NonNull<[u8]>, AllocError> {
+    self.0.allocate( layout.align_to( 32 ).map_err( |_| AllocError )? )
+  }
+
+  unsafe fn deallocate( &self, ptr: NonNull<u8>, layout: Layout ) {
+    // the allocation succeeded with this very layout
+    let layout = unsafe { Layout::from_size_align_unchecked( layout.size(), layout.align().max( 32 ) ) };
+    unsafe { self.0.deallocate( ptr, layout ) }
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(not(feature = "allocator-api2"))]
+mod std_alloc {
+  use std::ops::{Deref, DerefMut};
+  use crate::queue::Neighbor;
+
+  mod sealed {
+    pub trait Sealed {}
+  }
+
+  // only implemented by `Global` without the `allocator-api2` feature
+  pub trait Allocator: sealed::Sealed {}
+
+  #[derive(Debug, Clone, Copy, Default)]
+  pub struct Global;
+
+  impl sealed::Sealed for Global {}
+  impl Allocator for Global {}
+
+  // `Vec<Neighbor>` with the allocator constructors of `allocator_api2::vec::Vec`, everything else goes through `Deref`
+  pub struct Buffer<A> {
+    pub(super) vec: Vec<Neighbor>,
+    pub(super) alloc: A,
+  }
+
+  impl<A: Allocator> Buffer<A> {
+    pub fn new_in( alloc: A ) -> Self {
+      Self { vec: Vec::new(), alloc }
+    }
+
+    pub fn with_capacity_in( capacity: usize, alloc: A ) -> Self {
+      Self { vec: Vec::with_capacity( capacity ), alloc }
+    }
+
+    pub fn allocator( &self ) -> &A {
+      &self.alloc
+    }
+  }
+
+  impl<A> Deref for Buffer<A> {
+    type Target = Vec<Neighbor>;
+
+    #[inline(always)]
+    fn deref( &self ) -> &Vec<Neighbor> {
+      &self.vec
+    }
+  }
+
+  impl<A> DerefMut for Buffer<A> {
+    #[inline(always)]
+    fn deref_mut( &mut self ) -> &mut Vec<Neighbor> {
+      &mut self.vec
+    }
+  }
+}
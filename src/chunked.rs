@@ -0,0 +1,96 @@
+use std::panic;
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+use crate::queue::{Neighbor, Queue};
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// merges chunks of neighbors into a queue on a dedicated thread, so that producers can compute the next chunk while the
+// previous one is inserted. Chunks must be sorted in the queue order, the result is the same as inserting every chunk in
+// the order they were received.
+pub struct ChunkedInserter {
+  sender: Sender<Vec<Neighbor>>,
+  consumer: JoinHandle<Queue>,
+}
+
+impl ChunkedInserter {
+  pub fn new( queue: Queue ) -> Self {
+    let (sender, receiver) = mpsc::channel::<Vec<Neighbor>>();
+    let consumer = thread::spawn( move || {
+      let mut queue = queue;
+      for chunk in receiver {
+        queue.merge_sorted_iter( chunk.into_iter() );
+      }
+      queue
+    });
+    Self { sender, consumer }
+  }
+
+  // for producers living on other threads, every clone must be dropped before `finish`
+  pub fn sender( &self ) -> Sender<Vec<Neighbor>> {
+    self.sender.clone()
+  }
+
+  pub fn send( &self, chunk: Vec<Neighbor> ) {
+    // the receiver only goes away if the consumer panicked, which `finish` reports
+    _ = self.sender.send( chunk );
+  }
+
+  // waits for every chunk to be merged and returns the queue
+  pub fn finish( self ) -> Queue {
+    drop( self.sender );
+    match self.consumer.join() {
+      Ok( queue ) => queue,
+      Err( payload ) => panic::resume_unwind( payload ),
+    }
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::num::NonZeroUsize;
+  use rand::Rng;
+  use crate::queue::neighbor_cmp;
+  use crate::test_util::{bits, grid_neighbor, rng};
+
+  #[test]
+  fn matches_sequential_inserts() {
+    let mut rng = rng();
+    // sorted chunks that overlap and repeat neighbors
+    let chunks = (0..16).map( |_| {
+      let mut chunk = (0..rng.random_range( 0..40 )).map( |_| grid_neighbor( &mut rng ) ).collect::<Vec<_>>();
+      chunk.sort_by( neighbor_cmp );
+      chunk
+    }).collect::<Vec<_>>();
+
+    let capacity = NonZeroUsize::new( 24 ).unwrap();
+    let mut expected = Queue::with_capacity( capacity );
+    for chunk in &chunks {
+      for neighbor in chunk {
+        expected.insert( *neighbor );
+      }
+    }
+
+    let inserter = ChunkedInserter::new( Queue::with_capacity( capacity ) );
+    let sender = inserter.sender();
+    let producer = thread::spawn( move || {
+      for chunk in chunks {
+        sender.send( chunk ).unwrap();
+      }
+    });
+    producer.join().unwrap();
+    assert_eq!( bits( inserter.finish().as_slice() ), bits( expected.as_slice() ) );
+  }
+
+  #[test]
+  fn finish_without_chunks_returns_the_queue() {
+    let mut queue = Queue::with_capacity( NonZeroUsize::MIN );
+    queue.insert( Neighbor::new( 1, 0.5 ) );
+    let inserter = ChunkedInserter::new( queue );
+    inserter.send( Vec::new() );
+    assert_eq!( bits( inserter.finish().as_slice() ), bits( &[ Neighbor::new( 1, 0.5 ) ] ) );
+  }
+}
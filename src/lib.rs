@@ -1 +1,15 @@
+pub mod buffer;
+pub mod chunked;
+pub mod nearest;
+pub mod packed;
 pub mod queue;
+pub mod tiered;
+
+#[cfg(test)]
+mod test_util;
+
+#[cfg(feature = "testing")]
+pub mod reference;
+
+#[cfg(feature = "testing")]
+pub mod testing;
@@ -0,0 +1,101 @@
+use std::cmp::Ordering;
+use std::num::NonZeroUsize;
+use crate::queue::{neighbor_cmp, Neighbor};
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// the capacity 1 queue of a 1-NN search, picked at construction so that `Queue::insert` keeps a single path. There is no
+// search: the insert keeps the nearer of the two neighbors with a select instead of a branch, same result as
+// `Queue::insert` on a queue of capacity 1.
+#[derive(Debug, Clone)]
+pub struct NearestQueue {
+  best: Neighbor,
+  filled: bool,
+}
+
+impl NearestQueue {
+  pub fn new() -> Self {
+    Self { best: Neighbor::new( 0, 0.0 ), filled: false }
+  }
+
+  // empty or the nearest neighbor
+  pub fn as_slice( &self ) -> &[Neighbor] {
+    &std::slice::from_ref( &self.best )[..self.filled as usize]
+  }
+
+  pub fn best( &self ) -> Option<Neighbor> {
+    self.filled.then_some( self.best )
+  }
+
+  pub fn len( &self ) -> usize {
+    self.filled as usize
+  }
+
+  pub fn is_empty( &self ) -> bool {
+    !self.filled
+  }
+
+  pub fn capacity( &self ) -> NonZeroUsize {
+    NonZeroUsize::MIN
+  }
+}
+
+impl Default for NearestQueue {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl NearestQueue {
+  // the comparison is oriented as in the search of `Queue::insert`, so that NaN distances are handled the same way
+  #[inline(never)]
+  pub fn insert( &mut self, neighbor: Neighbor ) {
+    let nearer = !self.filled | (neighbor_cmp( &self.best, &neighbor ) == Ordering::Greater);
+    self.best = std::hint::select_unpredictable( nearer, neighbor, self.best );
+    self.filled = true;
+  }
+
+  pub fn clear( &mut self ) {
+    self.filled = false;
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::queue::Queue;
+  use crate::test_util::{bits, grid_neighbor_with, rng};
+
+  #[test]
+  fn matches_queue_of_capacity_1() {
+    let mut rng = rng();
+    let mut nearest = NearestQueue::new();
+    let mut queue = Queue::with_capacity( NonZeroUsize::MIN );
+    for _ in 0..200 {
+      nearest.clear();
+      queue.clear();
+      for _ in 0..50 {
+        let neighbor = grid_neighbor_with( &mut rng, &[ f32::NAN, -0.0 ] );
+        nearest.insert( neighbor );
+        queue.insert( neighbor );
+        assert_eq!( bits( nearest.as_slice() ), bits( queue.as_slice() ) );
+      }
+    }
+  }
+
+  #[test]
+  fn keeps_the_nearest() {
+    let mut nearest = NearestQueue::new();
+    assert!( nearest.is_empty() );
+    assert_eq!( nearest.best(), None );
+    nearest.insert( Neighbor::new( 3, 0.5 ) );
+    nearest.insert( Neighbor::new( 1, 0.75 ) );
+    nearest.insert( Neighbor::new( 2, 0.5 ) );
+    assert_eq!( nearest.best().map( |best| best.id ), Some( 2 ) );
+    assert_eq!( nearest.len(), 1 );
+    nearest.clear();
+    assert!( nearest.as_slice().is_empty() );
+  }
+}
@@ -0,0 +1,126 @@
+use std::num::NonZeroUsize;
+use crate::queue::Neighbor;
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// the distance bits go in the high half, flipped so that the unsigned order of the keys is the float order, and the id in
+// the low half: comparing two packed neighbors as `u64` compares them by distance then by id. -0.0 is packed as +0.0 to
+// keep the keys of equal distances equal, and NaNs sort at the ends.
+#[inline(always)]
+pub fn pack( neighbor: Neighbor ) -> u64 {
+  let bits = (neighbor.dist + 0.0).to_bits();
+  let key = if bits & 0x8000_0000 != 0 { !bits } else { bits | 0x8000_0000 };
+  (key as u64) << 32 | neighbor.id as u64
+}
+
+#[inline(always)]
+pub fn unpack( packed: u64 ) -> Neighbor {
+  let key = (packed >> 32) as u32;
+  let bits = if key & 0x8000_0000 != 0 { key & 0x7fff_ffff } else { !key };
+  Neighbor::new( packed as u32, f32::from_bits( bits ) )
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// same queue as `Queue` over packed neighbors: a single integer compare per search step
+pub struct PackedQueue {
+  neighbors: Vec<u64>,
+  capacity: NonZeroUsize,
+}
+
+impl PackedQueue {
+  pub fn with_capacity( capacity: NonZeroUsize ) -> Self {
+    let neighbors = Vec::with_capacity( capacity.get() );
+    Self { neighbors, capacity }
+  }
+
+  pub fn as_packed_slice( &self ) -> &[u64] {
+    &self.neighbors
+  }
+
+  pub fn iter( &self ) -> impl ExactSizeIterator<Item = Neighbor> + '_ {
+    self.neighbors.iter().map( |packed| unpack( *packed ) )
+  }
+
+  pub fn len( &self ) -> usize {
+    self.neighbors.len()
+  }
+
+  pub fn is_empty( &self ) -> bool {
+    self.neighbors.is_empty()
+  }
+
+  pub fn capacity( &self ) -> NonZeroUsize {
+    self.capacity
+  }
+}
+
+impl PackedQueue {
+  #[inline(never)]
+  pub fn insert( &mut self, neighbor: Neighbor ) {
+    let packed = pack( neighbor );
+    if let Err( pos ) = self.neighbors.binary_search( &packed ) && pos < self.capacity.get() {
+      if self.neighbors.len() == self.capacity.get() {
+        _ = self.neighbors.pop();
+      }
+      unsafe { std::hint::assert_unchecked( self.neighbors.len() < self.neighbors.capacity() ) };
+      self.neighbors.insert( pos, packed );
+    }
+  }
+
+  pub fn clear( &mut self ) {
+    self.neighbors.clear();
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::Rng;
+  use crate::queue::Queue;
+  use crate::test_util::{bits, grid_neighbor_with, rng};
+
+  #[test]
+  fn pack_round_trips() {
+    for dist in [ 0.0, -1.5, f32::MIN, f32::MIN_POSITIVE, 2.5, f32::MAX, f32::INFINITY, f32::NEG_INFINITY ] {
+      for id in [ 0, 7, u32::MAX ] {
+        let unpacked = unpack( pack( Neighbor::new( id, dist ) ) );
+        assert_eq!( (unpacked.id, unpacked.dist.to_bits()), (id, dist.to_bits()) );
+      }
+    }
+    // -0.0 comes back as +0.0
+    assert_eq!( unpack( pack( Neighbor::new( 3, -0.0 ) ) ).dist.to_bits(), 0.0f32.to_bits() );
+    assert_eq!( pack( Neighbor::new( 3, -0.0 ) ), pack( Neighbor::new( 3, 0.0 ) ) );
+  }
+
+  #[test]
+  fn pack_orders_by_distance_then_id() {
+    let dists = [ f32::NEG_INFINITY, -2.0, -0.5, -0.0, 0.0, 0.5, 2.0, f32::INFINITY ];
+    let neighbors = dists.iter().flat_map( |&dist| (0..3).map( move |id| Neighbor::new( id, dist ) ) ).collect::<Vec<_>>();
+    for a in &neighbors {
+      for b in &neighbors {
+        assert_eq!( pack( *a ).cmp( &pack( *b ) ), a.cmp( b ), "{a:?} {b:?}" );
+      }
+    }
+  }
+
+  #[test]
+  fn matches_queue() {
+    let mut rng = rng();
+    for _ in 0..200 {
+      let capacity = NonZeroUsize::new( rng.random_range( 1..16 ) ).unwrap();
+      let mut packed = PackedQueue::with_capacity( capacity );
+      let mut queue = Queue::with_capacity( capacity );
+      for _ in 0..50 {
+        let neighbor = grid_neighbor_with( &mut rng, &[ -0.0, -0.5, -2.0, f32::INFINITY, f32::NEG_INFINITY ] );
+        packed.insert( neighbor );
+        queue.insert( neighbor );
+        // `Queue` keeps the -0.0 it got first where `PackedQueue` keeps +0.0
+        let queued = queue.as_slice().iter().map( |neighbor| Neighbor::new( neighbor.id, neighbor.dist + 0.0 ) ).collect::<Vec<_>>();
+        assert_eq!( bits( &packed.iter().collect::<Vec<_>>() ), bits( &queued ) );
+      }
+    }
+  }
+}
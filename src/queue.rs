@@ -1,5 +1,11 @@
+use std::borrow::Cow;
 use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet, TryReserveError};
+use std::collections::binary_heap::PeekMut;
+use std::fmt;
 use std::num::NonZeroUsize;
+use std::ops::{Deref, DerefMut};
+use crate::buffer::{self, Allocator, Buffer, Global};
 
 // ---------------------------------------------------------------------------------------------------------------------------------
 
@@ -9,53 +15,2365 @@ pub struct Neighbor {
   pub dist: f32,
 }
 
+impl Neighbor {
+  pub const fn new( id: u32, dist: f32 ) -> Self {
+    Self { id, dist }
+  }
+}
+
+// total order for std containers such as `BinaryHeap`: by `total_cmp` distance, then by id. -0.0 is taken as +0.0, so it
+// only differs from `neighbor_cmp` on NaN distances.
+impl Ord for Neighbor {
+  fn cmp( &self, other: &Self ) -> Ordering {
+    (self.dist + 0.0).total_cmp( &(other.dist + 0.0) ).then( self.id.cmp( &other.id ) )
+  }
+}
+
+impl PartialOrd for Neighbor {
+  fn partial_cmp( &self, other: &Self ) -> Option<Ordering> {
+    Some( self.cmp( other ) )
+  }
+}
+
+impl PartialEq for Neighbor {
+  fn eq( &self, other: &Self ) -> bool {
+    self.cmp( other ) == Ordering::Equal
+  }
+}
+
+impl Eq for Neighbor {}
+
 // ---------------------------------------------------------------------------------------------------------------------------------
 
-pub struct Queue {
-  neighbors: Vec<Neighbor>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertControl {
+  Continue,
+  // the queue is full and no remaining candidate can beat its worst neighbor
+  Stop,
+}
+
+// the `[0, max]` distance range of `Queue::insert_clamped`, checked once at construction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClampRange {
+  max: f32,
+}
+
+impl ClampRange {
+  // None for a negative or NaN `max`, `inf` is fine and only clamps negative and NaN distances
+  pub fn new( max: f32 ) -> Option<Self> {
+    (max >= 0.0).then_some( Self { max } )
+  }
+
+  pub fn max( self ) -> f32 {
+    self.max
+  }
+
+  #[inline(always)]
+  fn clamp( self, dist: f32 ) -> f32 {
+    if dist.is_nan() { self.max } else { dist.clamp( 0.0, self.max ) }
+  }
+}
+
+// what `Queue::insert_evicting` did: the position of the inserted neighbor, and the farthest neighbor when it had to make
+// room
+enum Insertion {
+  Rejected,
+  Inserted( usize ),
+  Evicted( usize, Neighbor ),
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// the total order of the queue: by distance, then by id. Sort with it before feeding sorted inputs to the queue.
+//
+// this compare function emits conditional jumps in opt-level=2
+// but conditional moves in opt-level=3
+#[cfg(not(feature = "cmov-compare"))]
+#[inline(always)]
+pub fn neighbor_cmp( a: &Neighbor, b: &Neighbor ) -> Ordering {
+  if a.dist < b.dist { Ordering::Less }
+  else if a.dist == b.dist { a.id.cmp(&b.id) }
+  else { Ordering::Greater }
+}
+
+// this compare function emits conditional moves in opt-level=2 and 3
+// adding +0.0 turns -0.0 into +0.0, which `total_cmp` would otherwise order first
+#[cfg(feature = "cmov-compare")]
+#[inline(always)]
+pub fn neighbor_cmp( a: &Neighbor, b: &Neighbor ) -> Ordering {
+  match (a.dist + 0.0).total_cmp( &(b.dist + 0.0) ) {
+    Ordering::Equal => a.id.cmp( &b.id ),
+    ordering => ordering,
+  }
+}
+
+// how neighbors at the same distance are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+  #[default]
+  Id,
+  // by a hash of (id, seed): deterministic for a given seed but not biased toward small ids
+  Random( u64 ),
+}
+
+impl TieBreak {
+  #[inline(always)]
+  fn compare( self, other: &Neighbor, neighbor: &Neighbor ) -> Ordering {
+    match self {
+      TieBreak::Id => neighbor_cmp( other, neighbor ),
+      TieBreak::Random( seed ) => compare_seeded( other, neighbor, seed ),
+    }
+  }
+
+  // `compare` with the distances ordered by `total_cmp`, for the std sorts that panic on an order that is not total. Both
+  // only differ on NaN distances.
+  fn total_compare( self, a: &Neighbor, b: &Neighbor ) -> Ordering {
+    match self {
+      TieBreak::Id => a.cmp( b ),
+      TieBreak::Random( seed ) => (a.dist + 0.0).total_cmp( &(b.dist + 0.0) )
+        .then_with( || (mix( a.id, seed ), a.id).cmp( &(mix( b.id, seed ), b.id) ) ),
+    }
+  }
+}
+
+#[inline(always)]
+fn compare_seeded( other: &Neighbor, neighbor: &Neighbor, seed: u64 ) -> Ordering {
+  if other.dist == neighbor.dist {
+    (mix( other.id, seed ), other.id).cmp( &(mix( neighbor.id, seed ), neighbor.id) )
+  } else {
+    neighbor_cmp( other, neighbor )
+  }
+}
+
+// splitmix64 finalizer
+#[inline(always)]
+fn mix( id: u32, seed: u64 ) -> u64 {
+  let mut z = (id as u64 ^ seed).wrapping_add( 0x9e37_79b9_7f4a_7c15 );
+  z = (z ^ (z >> 30)).wrapping_mul( 0xbf58_476d_1ce4_e5b9 );
+  z = (z ^ (z >> 27)).wrapping_mul( 0x94d0_49bb_1331_11eb );
+  z ^ (z >> 31)
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CapacityError {
+  // more than `Queue::MAX_CAPACITY` neighbors
+  TooLarge { capacity: usize },
+  // the allocator could not provide the buffer
+  Alloc( TryReserveError ),
+}
+
+impl fmt::Display for CapacityError {
+  fn fmt( &self, f: &mut fmt::Formatter<'_> ) -> fmt::Result {
+    match self {
+      CapacityError::TooLarge { capacity } =>
+        write!( f, "capacity of {capacity} neighbors exceeds the maximum of {}", Queue::MAX_CAPACITY ),
+      CapacityError::Alloc( error ) => write!( f, "queue allocation failed: {error}" ),
+    }
+  }
+}
+
+impl std::error::Error for CapacityError {}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// how an unbounded queue grows its allocation once it is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Growth {
+  #[default]
+  Doubling,
+  Fixed( NonZeroUsize ),
+  Exact,
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// the buffer is allocated with `A`, see `crate::buffer`
+pub struct Queue<A: Allocator = Global> {
+  neighbors: Buffer<A>,
   capacity: NonZeroUsize,
+  tie_break: TieBreak,
+  growth: Growth,
+  // consecutive inserts rejected by the full queue
+  #[cfg(feature = "metrics")]
+  rejected: usize,
+  // set by `swap_remove` until `resort`, only tracked to catch sorted reads on a broken queue
+  #[cfg(debug_assertions)]
+  unsorted: bool,
 }
 
 impl Queue {
+  // a larger allocation would exceed `isize::MAX` bytes
+  pub const MAX_CAPACITY: usize = isize::MAX as usize / size_of::<Neighbor>();
+
   pub fn with_capacity( capacity: NonZeroUsize ) -> Self {
-    let neighbors = Vec::with_capacity( capacity.get() );
-    Self { neighbors, capacity }
+    Self::with_capacity_in( capacity, Global )
+  }
+
+  // same as `with_capacity` but a capacity above `MAX_CAPACITY` or a failed allocation is an error instead of a panic or
+  // an abort
+  pub fn try_with_capacity( capacity: NonZeroUsize ) -> Result<Self, CapacityError> {
+    if capacity.get() > Self::MAX_CAPACITY {
+      return Err( CapacityError::TooLarge { capacity: capacity.get() } );
+    }
+    let mut neighbors = Vec::new();
+    neighbors.try_reserve_exact( capacity.get() ).map_err( CapacityError::Alloc )?;
+    Ok( Self::from_parts( buffer::from_vec( neighbors ), capacity ) )
+  }
+
+  // the nearest `capacity` neighbors of the heap, reusing its allocation
+  pub fn from_heap( capacity: NonZeroUsize, heap: BinaryHeap<Neighbor> ) -> Self {
+    Self::from( (capacity, heap.into_vec()) )
+  }
+
+  // see `with_capacity_power_of_two_in`, the alignment is the one of `Neighbor`
+  pub fn with_capacity_power_of_two( capacity: NonZeroUsize ) -> Self {
+    Self::with_capacity_power_of_two_in( capacity, Global )
+  }
+
+  // planning heuristic for a search over `dataset_len` points: `log2(dataset_len + 1) / (1 - target_recall)`, with
+  // `target_recall` clamped to [0, 0.999] (NaN counts as 0), then bounded to [1, dataset_len]. The capacity grows with the
+  // target recall and only logarithmically with the dataset.
+  pub fn suggest_capacity( dataset_len: usize, target_recall: f32 ) -> NonZeroUsize {
+    let recall = if target_recall.is_nan() { 0.0 } else { target_recall.clamp( 0.0, 0.999 ) };
+    let capacity = ((dataset_len as f64 + 1.0).log2() / (1.0 - recall as f64)).ceil() as usize;
+    NonZeroUsize::new( capacity.min( dataset_len ) ).unwrap_or( NonZeroUsize::MIN )
+  }
+
+  // sorted multiset that never evicts, its allocation grows following `growth`
+  pub fn unbounded( growth: Growth ) -> Self {
+    Self { growth, ..Self::from_parts( Buffer::new_in( Global ), NonZeroUsize::MAX ) }
+  }
+
+  pub fn with_tie_break( capacity: NonZeroUsize, tie_break: TieBreak ) -> Self {
+    Self { tie_break, ..Self::with_capacity( capacity ) }
+  }
+}
+
+impl<A: Allocator> Queue<A> {
+  pub fn with_capacity_in( capacity: NonZeroUsize, alloc: A ) -> Self {
+    let neighbors = Buffer::with_capacity_in( capacity.get(), alloc );
+    Self::from_parts( neighbors, capacity )
+  }
+
+  // the allocation is rounded up to a power of two neighbors while `capacity` still bounds the queue. With the
+  // `allocator-api2` feature, `buffer::Aligned32` aligns it to 32 bytes for SIMD loads.
+  pub fn with_capacity_power_of_two_in( capacity: NonZeroUsize, alloc: A ) -> Self {
+    let allocated = capacity.checked_next_power_of_two().unwrap_or( capacity );
+    let neighbors = Buffer::with_capacity_in( allocated.get(), alloc );
+    Self::from_parts( neighbors, capacity )
+  }
+
+  // `neighbors` must be sorted and hold at most `capacity` neighbors. It may have room for fewer than `capacity`, as the
+  // empty buffer of `unbounded`, since `insert_at` grows it when it is full.
+  fn from_parts( neighbors: Buffer<A>, capacity: NonZeroUsize ) -> Self {
+    Self {
+      neighbors,
+      capacity,
+      tie_break: TieBreak::Id,
+      growth: Growth::Doubling,
+      #[cfg(feature = "metrics")]
+      rejected: 0,
+      #[cfg(debug_assertions)]
+      unsorted: false,
+    }
   }
 
   pub fn as_slice( &self ) -> &[Neighbor] {
+    self.debug_assert_sorted();
     &self.neighbors
   }
+
+  // borrowed view for generic code that may need to own the results
+  pub fn as_sorted( &self ) -> Cow<'_, [Neighbor]> {
+    Cow::Borrowed( self.as_slice() )
+  }
+
+  /// The `len` nearest neighbors, without bounds check.
+  ///
+  /// # Safety
+  ///
+  /// `len` must be lower or equal to `self.len()`.
+  pub unsafe fn as_slice_unchecked( &self, len: usize ) -> &[Neighbor] {
+    self.debug_assert_sorted();
+    debug_assert!( len <= self.neighbors.len() );
+    unsafe { self.neighbors.get_unchecked( ..len ) }
+  }
+
+  pub fn len( &self ) -> usize {
+    self.neighbors.len()
+  }
+
+  pub fn is_empty( &self ) -> bool {
+    self.neighbors.is_empty()
+  }
+
+  // the neighbors are non-decreasing in the queue order
+  pub fn is_sorted( &self ) -> bool {
+    let tie_break = self.tie_break;
+    self.neighbors.is_sorted_by( |a, b| tie_break.compare( a, b ) != Ordering::Greater )
+  }
+
+  pub fn capacity( &self ) -> NonZeroUsize {
+    self.capacity
+  }
+
+  // number of neighbors the backing allocation can hold, it is the highest capacity ever set on the queue
+  pub fn allocated_capacity( &self ) -> usize {
+    self.neighbors.capacity()
+  }
+
+  pub fn capacity_bytes( &self ) -> usize {
+    self.neighbors.capacity() * size_of::<Neighbor>()
+  }
+
+  pub fn view( &self ) -> QueueView<'_> {
+    self.debug_assert_sorted();
+    QueueView{ neighbors: &self.neighbors }
+  }
+
+  // distance of the k-th nearest neighbor, `k` counting from 1
+  pub fn kth_dist( &self, k: usize ) -> Option<f32> {
+    self.view().kth_dist( k )
+  }
+
+  // distances in sorted order as `N`-lane chunks plus the remainder. The buffer interleaves ids and distances, so the lanes
+  // are gathered on the fly instead of borrowed.
+  pub fn dist_chunks<const N: usize>( &self )
+    -> (impl ExactSizeIterator<Item = [f32; N]> + '_, impl ExactSizeIterator<Item = f32> + '_)
+  {
+    const { assert!( N > 0 ) };
+    self.debug_assert_sorted();
+    let chunks = self.neighbors.chunks_exact( N );
+    let remainder = chunks.remainder().iter().map( |neighbor| neighbor.dist );
+    (chunks.map( |chunk| std::array::from_fn( |lane| chunk[lane].dist ) ), remainder)
+  }
+
+  // copy of the neighbors ordered by id, e.g. to merge-join with id-sorted data
+  pub fn to_id_sorted_vec( &self ) -> Vec<Neighbor> {
+    let mut neighbors = self.neighbors.to_vec();
+    neighbors.sort_by_key( |neighbor| neighbor.id );
+    neighbors
+  }
+
+  // the `n` farthest neighbors in sorted order, `n` is clamped to the number of neighbors
+  pub fn worst_n( &self, n: usize ) -> &[Neighbor] {
+    self.debug_assert_sorted();
+    &self.neighbors[self.neighbors.len().saturating_sub( n )..]
+  }
+
+  // same ids at the same ranks and distances within `epsilon` of each other
+  pub fn approx_eq<B: Allocator>( &self, other: &Queue<B>, epsilon: f32 ) -> bool {
+    self.debug_assert_sorted();
+    other.debug_assert_sorted();
+    self.neighbors.len() == other.neighbors.len()
+      && self.neighbors.iter().zip( other.neighbors.iter() ).all( |(a, b)| a.id == b.id && (a.dist - b.dist).abs() <= epsilon )
+  }
+
+  // (ids only in `self`, ids only in `other`), both sorted
+  pub fn diff<B: Allocator>( &self, other: &Queue<B> ) -> (Vec<u32>, Vec<u32>) {
+    let sorted_ids = |neighbors: &[Neighbor]| {
+      let mut ids = neighbors.iter().map( |neighbor| neighbor.id ).collect::<Vec<_>>();
+      ids.sort_unstable();
+      ids.dedup();
+      ids
+    };
+    let (ours, theirs) = (sorted_ids( &self.neighbors ), sorted_ids( &other.neighbors ));
+
+    let (mut added, mut removed) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < ours.len() && j < theirs.len() {
+      match ours[i].cmp( &theirs[j] ) {
+        Ordering::Less => { added.push( ours[i] ); i += 1; }
+        Ordering::Equal => { i += 1; j += 1; }
+        Ordering::Greater => { removed.push( theirs[j] ); j += 1; }
+      }
+    }
+    added.extend_from_slice( &ours[i..] );
+    removed.extend_from_slice( &theirs[j..] );
+    (added, removed)
+  }
+
+  // fraction of `truth_ids` held by the queue, 1.0 for an empty `truth_ids` as there is nothing to miss
+  pub fn recall_against( &self, truth_ids: &[u32] ) -> f32 {
+    if truth_ids.is_empty() {
+      return 1.0;
+    }
+    let ids = self.neighbors.iter().map( |neighbor| neighbor.id ).collect::<HashSet<_>>();
+    let found = truth_ids.iter().filter( |id| ids.contains( id ) ).count();
+    found as f32 / truth_ids.len() as f32
+  }
+
+  // average overlap of the two rankings: the mean over the depths d of |top-d(self) ∩ top-d(other)| / d, with d up to the
+  // longest queue. 1.0 for identical rankings (or two empty queues), 0.0 for disjoint ones, and early ranks weigh more
+  // since they count at every depth.
+  pub fn stability_score<B: Allocator>( &self, other: &Queue<B> ) -> f32 {
+    self.debug_assert_sorted();
+    other.debug_assert_sorted();
+    let depth = self.neighbors.len().max( other.neighbors.len() );
+    if depth == 0 {
+      return 1.0;
+    }
+
+    let (mut ours, mut theirs) = (HashSet::with_capacity( depth ), HashSet::with_capacity( depth ));
+    let mut overlap = 0usize;
+    let mut sum = 0.0f64;
+    for d in 0..depth {
+      let a = self.neighbors.get( d ).map( |neighbor| neighbor.id );
+      let b = other.neighbors.get( d ).map( |neighbor| neighbor.id );
+      if let Some( a ) = a && ours.insert( a ) && theirs.contains( &a ) {
+        overlap += 1;
+      }
+      if let Some( b ) = b && theirs.insert( b ) && ours.contains( &b ) {
+        overlap += 1;
+      }
+      sum += overlap as f64 / (d + 1) as f64;
+    }
+    (sum / depth as f64) as f32
+  }
+
+  pub fn capacity_utilization( &self ) -> f32 {
+    self.neighbors.len() as f32 / self.capacity.get() as f32
+  }
+
+  // the queue is full and none of the last `n` inserts got in, a hint that the search may stop
+  #[cfg(feature = "metrics")]
+  pub fn is_saturated_since( &self, n: usize ) -> bool {
+    self.neighbors.len() == self.capacity.get() && self.rejected >= n
+  }
 }
 
-impl Queue {
+impl<A: Allocator> Queue<A> {
+  // unlike `insert`, this one is meant to be inlined in the caller loop
+  #[inline]
+  pub fn accepts( &self, dist: f32 ) -> bool {
+    match self.neighbors.last() {
+      Some( worst ) if self.neighbors.len() == self.capacity.get() => dist < worst.dist,
+      _ => true,
+    }
+  }
+
+  // inlined gate in front of the out-of-line `insert`, so that the common reject does not pay for a call:
+  //
+  //   if queue.try_reject( neighbor.dist ) { continue }
+  //   queue.insert( neighbor );
+  //
+  // it only rejects what `insert` would reject too, so both ways give the same queue
+  #[inline(always)]
+  pub fn try_reject( &self, dist: f32 ) -> bool {
+    match self.neighbors.last() {
+      Some( worst ) if self.neighbors.len() == self.capacity.get() => dist > worst.dist,
+      _ => false,
+    }
+  }
+
   #[inline(never)]
   pub fn insert( &mut self, neighbor: Neighbor ) {
-    // this compare function emits conditional jumps in opt-level=2
-    // but conditional moves in opt-level=3
-    let cmp = |other: &Neighbor| -> Ordering {
-      if other.dist < neighbor.dist { Ordering::Less }
-      else if other.dist == neighbor.dist { other.id.cmp(&neighbor.id) }
-      else { Ordering::Greater }
+    _ = self.insert_evicting( neighbor );
+  }
+
+  // pushes the id of the neighbor evicted by the insert, if any, to `evicted_out`
+  pub fn insert_reporting( &mut self, neighbor: Neighbor, evicted_out: &mut Vec<u32> ) {
+    if let Insertion::Evicted( _, evicted ) = self.insert_evicting( neighbor ) {
+      evicted_out.push( evicted.id );
+    }
+  }
+
+  // body of `insert`
+  #[inline(always)]
+  fn insert_evicting( &mut self, neighbor: Neighbor ) -> Insertion {
+    self.debug_assert_sorted();
+    let search = match self.tie_break {
+      TieBreak::Id => self.neighbors.binary_search_by( |other| neighbor_cmp( other, &neighbor ) ),
+      TieBreak::Random( seed ) => self.neighbors.binary_search_by( |other| compare_seeded( other, &neighbor, seed ) ),
+    };
+
+    if let Err( pos ) = search && pos < self.capacity.get() {
+      let insertion = match self.neighbors.last() {
+        Some( worst ) if self.neighbors.len() == self.capacity.get() => Insertion::Evicted( pos, *worst ),
+        _ => Insertion::Inserted( pos ),
+      };
+      self.insert_at( pos, neighbor );
+      insertion
+    } else {
+      self.reject();
+      Insertion::Rejected
+    }
+  }
+
+  // lets the caller keep both scalars in registers up to the call
+  #[inline]
+  pub fn insert_id_dist( &mut self, id: u32, dist: f32 ) {
+    self.insert( Neighbor::new( id, dist ) );
+  }
+
+  // the neighbor is ranked and evicted on its effective distance `raw_dist * weight`, which is the one stored
+  pub fn insert_weighted( &mut self, id: u32, raw_dist: f32, weight: f32 ) {
+    self.insert( Neighbor::new( id, raw_dist * weight ) );
+  }
+
+  // the distance is clamped to `range` before the insert: negative distances become 0, `inf` and NaN become its max
+  pub fn insert_clamped( &mut self, neighbor: Neighbor, range: ClampRange ) {
+    self.insert( Neighbor{ dist: range.clamp( neighbor.dist ), ..neighbor } );
+  }
+
+  // `remaining_lower_bound` is a lower bound on the distance of every candidate left to insert
+  pub fn insert_with_bound( &mut self, neighbor: Neighbor, remaining_lower_bound: f32 ) -> InsertControl {
+    self.insert( neighbor );
+    match self.neighbors.last() {
+      Some( worst ) if self.neighbors.len() == self.capacity.get() && remaining_lower_bound >= worst.dist => InsertControl::Stop,
+      _ => InsertControl::Continue,
+    }
+  }
+
+  // same result as `insert` but the position is searched by galloping away from `hint` before narrowing it with a binary
+  // search, which is cheaper when consecutive inserts land close to each other. The returned position is the one the
+  // neighbor was (or would have been) inserted at and is meant to be fed back as the next hint.
+  pub fn insert_hinted( &mut self, neighbor: Neighbor, hint: usize ) -> usize {
+    self.debug_assert_sorted();
+    let tie_break = self.tie_break;
+    let less = |other: &Neighbor| tie_break.compare( other, &neighbor ) == Ordering::Less;
+    let neighbors = &self.neighbors;
+    let len = neighbors.len();
+    let hint = hint.min( len );
+
+    let (lo, hi) = if hint < len && less( &neighbors[hint] ) {
+      let mut lo = hint + 1;
+      let mut step = 1;
+      let hi = loop {
+        let probe = lo + step - 1;
+        if probe >= len { break len; }
+        if !less( &neighbors[probe] ) { break probe; }
+        lo = probe + 1;
+        step *= 2;
+      };
+      (lo, hi)
+    } else {
+      let mut hi = hint;
+      let mut step = 1;
+      let lo = loop {
+        if hi == 0 { break 0; }
+        let probe = hi.saturating_sub( step );
+        if less( &neighbors[probe] ) { break probe + 1; }
+        hi = probe;
+        step *= 2;
+      };
+      (lo, hi)
     };
 
-    // this compare function emits conditional moves in opt-level=2 and 3
-    // let cmp = |other: &Neighbor| -> Ordering {
-    //   match other.dist.total_cmp( &neighbor.dist ) {
-    //     Ordering::Equal => other.id.cmp( &neighbor.id ),
-    //     ordering => ordering,
-    //   }
-    // };
+    let pos = lo + neighbors[lo..hi].partition_point( less );
+    let duplicate = neighbors.get( pos ).is_some_and( |other| tie_break.compare( other, &neighbor ) == Ordering::Equal );
+    if !duplicate && pos < self.capacity.get() {
+      self.insert_at( pos, neighbor );
+    } else {
+      self.reject();
+    }
+    pos
+  }
+
+  // upsert on `id`: an existing neighbor with the same id is replaced by `neighbor` whatever their distances, and its
+  // previous distance is returned
+  pub fn insert_or_replace( &mut self, neighbor: Neighbor ) -> Option<f32> {
+    let previous = self.neighbors.iter().position( |other| other.id == neighbor.id )
+      .map( |pos| self.neighbors.remove( pos ).dist );
+    self.insert( neighbor );
+    previous
+  }
+
+  #[inline(always)]
+  fn insert_at( &mut self, pos: usize, neighbor: Neighbor ) {
+    if self.neighbors.len() == self.capacity.get() {
+      _ = self.neighbors.pop();
+    }
+    // only an unbounded queue can run out of room
+    if self.neighbors.len() == self.neighbors.capacity() {
+      self.grow();
+    }
+    unsafe { std::hint::assert_unchecked( self.neighbors.len() < self.neighbors.capacity() ) };
+    self.neighbors.insert( pos, neighbor );
+    #[cfg(feature = "metrics")]
+    { self.rejected = 0; }
+  }
+
+  #[cold]
+  #[inline(never)]
+  fn grow( &mut self ) {
+    match self.growth {
+      Growth::Doubling => self.neighbors.reserve( 1 ),
+      Growth::Fixed( step ) => self.neighbors.reserve_exact( step.get() ),
+      Growth::Exact => self.neighbors.reserve_exact( 1 ),
+    }
+  }
+
+  #[inline(always)]
+  fn reject( &mut self ) {
+    #[cfg(feature = "metrics")]
+    if self.neighbors.len() == self.capacity.get() {
+      self.rejected += 1;
+    }
+  }
+
+  // `iter` must be sorted in the queue order: once a full queue rejects a neighbor, none of the following ones can get in
+  // and the iterator is not consumed any further
+  pub fn merge_sorted_iter( &mut self, iter: impl Iterator<Item = Neighbor> ) {
+    for neighbor in iter {
+      if self.neighbors.len() == self.capacity.get()
+        && let Some( worst ) = self.neighbors.last()
+        && self.tie_break.compare( &neighbor, worst ) != Ordering::Less {
+        self.reject();
+        break;
+      }
+      self.insert( neighbor );
+    }
+  }
+
+  // `ascending` must be sorted in the queue order, e.g. a scan of a sorted index. Neighbors past the current worst are
+  // appended without any search and the run stops at the first one rejected by the full queue.
+  pub fn insert_sorted_run( &mut self, ascending: impl Iterator<Item = Neighbor> ) {
+    self.debug_assert_sorted();
+    for neighbor in ascending {
+      let Some( worst ) = self.neighbors.last() else {
+        self.insert_at( 0, neighbor );
+        continue;
+      };
+      match self.tie_break.compare( worst, &neighbor ) {
+        Ordering::Less if self.neighbors.len() < self.capacity.get() => self.insert_at( self.neighbors.len(), neighbor ),
+        Ordering::Less => {
+          self.reject();
+          break;
+        }
+        Ordering::Equal => self.reject(),
+        Ordering::Greater => self.insert( neighbor ),
+      }
+    }
+  }
+
+  // `sorted` must be sorted in the queue order. Both inputs are merged in one pass keeping only the nearest neighbor of
+  // each id, then bounded to the capacity.
+  pub fn merge_sorted_dedup( &mut self, sorted: &[Neighbor] ) where A: Clone {
+    self.debug_assert_sorted();
+    let tie_break = self.tie_break;
+    debug_assert!( sorted.is_sorted_by( |a, b| tie_break.compare( a, b ) != Ordering::Greater ) );
 
-    if let Err( pos ) = self.neighbors.binary_search_by( cmp ) && pos < self.capacity.get() {
-      if self.neighbors.len() == self.capacity.get() {
-        _ = self.neighbors.pop();
+    let capacity = self.capacity.get();
+    let mut merged = Buffer::with_capacity_in( self.neighbors.capacity(), self.neighbors.allocator().clone() );
+    let mut seen = HashSet::with_capacity( (self.neighbors.len() + sorted.len()).min( capacity ) );
+    let (mut left, mut right) = (self.neighbors.as_slice(), sorted);
+    while merged.len() < capacity {
+      let neighbor = match (left.split_first(), right.split_first()) {
+        (Some( (a, _) ), Some( (b, rest) )) if tie_break.compare( a, b ) == Ordering::Greater => { right = rest; *b }
+        (Some( (a, rest) ), _) => { left = rest; *a }
+        (None, Some( (b, rest) )) => { right = rest; *b }
+        (None, None) => break,
+      };
+      if seen.insert( neighbor.id ) {
+        merged.push( neighbor );
       }
-      unsafe { std::hint::assert_unchecked( self.neighbors.len() < self.neighbors.capacity() ) };
-      self.neighbors.insert( pos, neighbor );
     }
+    self.neighbors = merged;
+  }
+
+  // perf hint with no semantic effect: writes over the unused part of the allocation so that its pages are committed
+  // before a latency sensitive search, instead of faulting during the first inserts
+  pub fn prewarm( &mut self ) {
+    let spare = self.neighbors.spare_capacity_mut();
+    for slot in spare.iter_mut() {
+      slot.write( Neighbor::new( 0, 0.0 ) );
+    }
+    std::hint::black_box( spare );
+  }
+
+  // range query merge: keeps the neighbors of both queues within `radius` (inclusive), bounded to the capacity
+  pub fn merge_within<B: Allocator>( &mut self, other: &Queue<B>, radius: f32 ) {
+    self.debug_assert_sorted();
+    let within = self.neighbors.partition_point( |neighbor| neighbor.dist <= radius );
+    self.neighbors.truncate( within );
+    for neighbor in other.as_slice().iter().take_while( |neighbor| neighbor.dist <= radius ) {
+      self.insert( *neighbor );
+    }
+  }
+
+  // lowering the capacity drops the farthest neighbors but the allocation is never shrunk, so that cycling between
+  // capacities only reallocates when a new highest capacity is reached
+  pub fn set_capacity( &mut self, capacity: NonZeroUsize ) {
+    self.debug_assert_sorted();
+    self.neighbors.truncate( capacity.get() );
+    if capacity.get() > self.neighbors.capacity() {
+      let additional = capacity.get() - self.neighbors.len();
+      self.neighbors.reserve_exact( additional );
+    }
+    self.capacity = capacity;
+  }
+
+  pub fn peek_worst_mut( &mut self ) -> Option<WorstGuard<'_, A>> {
+    self.debug_assert_sorted();
+    let original = *self.neighbors.last()?;
+    Some( WorstGuard{ queue: self, original } )
+  }
+
+  pub fn reserve_exact( &mut self, additional: usize ) {
+    self.neighbors.reserve_exact( additional );
+  }
+
+  // moves the sorted neighbors at the end of `out`, the queue keeps its allocation
+  pub fn drain_into( &mut self, out: &mut Vec<Neighbor> ) {
+    self.debug_assert_sorted();
+    out.extend_from_slice( &self.neighbors );
+    self.clear();
+  }
+
+  // removes the `n` farthest neighbors and returns them in sorted order, `n` is clamped to the number of neighbors
+  pub fn drain_worst_n( &mut self, n: usize ) -> Vec<Neighbor> {
+    self.debug_assert_sorted();
+    let start = self.neighbors.len().saturating_sub( n );
+    let worst = self.neighbors[start..].to_vec();
+    self.neighbors.truncate( start );
+    worst
+  }
+
+  // O(1) removal that moves the last neighbor to `index`.
+  //
+  // WARNING: this BREAKS the sort order of the queue. Nothing but `swap_remove`, `resort` and `clear` may be called until
+  // the order is restored with `resort`, debug builds panic on sorted reads in between.
+  pub fn swap_remove( &mut self, index: usize ) -> Neighbor {
+    #[cfg(debug_assertions)]
+    { self.unsorted = true; }
+    self.neighbors.swap_remove( index )
+  }
+
+  pub fn resort( &mut self ) {
+    let tie_break = self.tie_break;
+    self.neighbors.sort_unstable_by( |a, b| tie_break.total_compare( a, b ) );
+    #[cfg(debug_assertions)]
+    { self.unsorted = false; }
   }
 
   pub fn clear( &mut self ) {
     self.neighbors.clear();
+    #[cfg(feature = "metrics")]
+    { self.rejected = 0; }
+    #[cfg(debug_assertions)]
+    { self.unsorted = false; }
+  }
+
+  // `clear` that also overwrites the whole allocation with zeros, so that a reused buffer never holds the neighbors of a
+  // previous query. Volatile writes keep the compiler from dropping the stores to memory it sees as dead.
+  pub fn clear_zeroed( &mut self ) {
+    self.clear();
+    for slot in self.neighbors.spare_capacity_mut() {
+      unsafe { std::ptr::write_volatile( slot.as_mut_ptr(), Neighbor::new( 0, 0.0 ) ) };
+    }
+    std::sync::atomic::compiler_fence( std::sync::atomic::Ordering::SeqCst );
+  }
+
+  // reset for a query of possibly different capacity, only reallocates to grow past the allocated capacity
+  pub fn reset( &mut self, capacity: NonZeroUsize ) {
+    self.clear();
+    if capacity != self.capacity {
+      self.set_capacity( capacity );
+    }
+  }
+
+  // compact copy of the state of the queue, for backtracking with `restore`
+  pub fn snapshot( &self ) -> Snapshot {
+    self.debug_assert_sorted();
+    Snapshot{ neighbors: self.neighbors.to_vec(), capacity: self.capacity }
+  }
+
+  // rolls back to `snapshot` in the current allocation
+  pub fn restore( &mut self, snapshot: &Snapshot ) {
+    self.reset( snapshot.capacity );
+    self.neighbors.extend_from_slice( &snapshot.neighbors );
+  }
+
+  // reset for the next round of an incremental search, seeded with the `n` nearest neighbors of this one
+  pub fn clear_retaining_best( &mut self, n: usize ) {
+    self.debug_assert_sorted();
+    self.neighbors.truncate( n );
+    #[cfg(feature = "metrics")]
+    { self.rejected = 0; }
+  }
+
+  // cleanup pass after a bulk merge: only the first, nearest, neighbor of each id is kept, in order
+  pub fn dedup_keep_best_by_id( &mut self ) {
+    self.retain_best_per_group( |neighbor| neighbor.id );
+  }
+
+  // only the first, nearest, neighbor of each group is kept, in order, e.g. one result per cluster
+  pub fn retain_best_per_group( &mut self, group_of: impl Fn( &Neighbor ) -> u32 ) {
+    self.debug_assert_sorted();
+    let mut seen = HashSet::with_capacity( self.neighbors.len() );
+    self.neighbors.retain( |neighbor| seen.insert( group_of( neighbor ) ) );
+  }
+
+  #[inline(always)]
+  fn debug_assert_sorted( &self ) {
+    #[cfg(debug_assertions)]
+    debug_assert!( !self.unsorted, "sorted read on a queue broken by swap_remove, call resort first" );
+  }
+}
+
+// the conversions from and to `Vec` only exist for the `Global` allocator
+impl Queue {
+  // the allocation is shrunk to the number of neighbors
+  pub fn into_boxed_slice( self ) -> Box<[Neighbor]> {
+    self.debug_assert_sorted();
+    buffer::into_vec( self.neighbors ).into_boxed_slice()
+  }
+
+  // (the `k` nearest neighbors, the others), `k` is clamped to the number of neighbors
+  pub fn split_at_rank( self, k: usize ) -> (Vec<Neighbor>, Vec<Neighbor>) {
+    self.debug_assert_sorted();
+    let mut nearest = buffer::into_vec( self.neighbors );
+    let rest = nearest.split_off( k.min( nearest.len() ) );
+    (nearest, rest)
+  }
+
+  // `reserve` that reports a failed allocation instead of aborting
+  pub fn try_reserve( &mut self, additional: usize ) -> Result<(), TryReserveError> {
+    let mut neighbors = buffer::into_vec( std::mem::replace( &mut self.neighbors, Buffer::new_in( Global ) ) );
+    let reserved = neighbors.try_reserve( additional );
+    self.neighbors = buffer::from_vec( neighbors );
+    reserved
+  }
+}
+
+// read-only sorted neighbors, whether they come from a `Queue` or from a slice sorted with `neighbor_cmp`
+#[derive(Debug, Clone, Copy)]
+pub struct QueueView<'a> {
+  neighbors: &'a [Neighbor],
+}
+
+impl<'a> QueueView<'a> {
+  pub fn new( neighbors: &'a [Neighbor] ) -> Self {
+    debug_assert!( neighbors.is_sorted_by( |a, b| neighbor_cmp( a, b ) != Ordering::Greater ) );
+    Self { neighbors }
+  }
+
+  pub fn as_slice( &self ) -> &'a [Neighbor] {
+    self.neighbors
+  }
+
+  pub fn len( &self ) -> usize {
+    self.neighbors.len()
+  }
+
+  pub fn is_empty( &self ) -> bool {
+    self.neighbors.is_empty()
+  }
+
+  pub fn iter( &self ) -> std::slice::Iter<'a, Neighbor> {
+    self.neighbors.iter()
+  }
+
+  pub fn best( &self ) -> Option<&'a Neighbor> {
+    self.neighbors.first()
+  }
+
+  pub fn peek_worst( &self ) -> Option<&'a Neighbor> {
+    self.neighbors.last()
+  }
+
+  // neighbors with a distance in `[min, max)`
+  pub fn range( &self, min: f32, max: f32 ) -> &'a [Neighbor] {
+    let start = self.neighbors.partition_point( |neighbor| neighbor.dist < min );
+    let end = self.neighbors.partition_point( |neighbor| neighbor.dist < max ).max( start );
+    &self.neighbors[start..end]
+  }
+
+  // number of neighbors within `radius` (inclusive)
+  pub fn count_within( &self, radius: f32 ) -> usize {
+    self.neighbors.partition_point( |neighbor| neighbor.dist <= radius )
+  }
+
+  // distance of the k-th nearest neighbor, `k` counting from 1
+  pub fn kth_dist( &self, k: usize ) -> Option<f32> {
+    k.checked_sub( 1 ).and_then( |index| self.neighbors.get( index ) ).map( |neighbor| neighbor.dist )
+  }
+}
+
+impl<'a> IntoIterator for QueueView<'a> {
+  type Item = &'a Neighbor;
+  type IntoIter = std::slice::Iter<'a, Neighbor>;
+
+  fn into_iter( self ) -> Self::IntoIter {
+    self.neighbors.iter()
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+  neighbors: Vec<Neighbor>,
+  capacity: NonZeroUsize,
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// mutable access to the farthest neighbor. On drop, a neighbor that got nearer is moved back to its sorted position, one
+// that got farther is evicted and an unchanged one is left in place.
+pub struct WorstGuard<'a, A: Allocator = Global> {
+  queue: &'a mut Queue<A>,
+  original: Neighbor,
+}
+
+impl<A: Allocator> Deref for WorstGuard<'_, A> {
+  type Target = Neighbor;
+
+  fn deref( &self ) -> &Neighbor {
+    let last = self.queue.neighbors.len() - 1;
+    &self.queue.neighbors[last]
+  }
+}
+
+impl<A: Allocator> DerefMut for WorstGuard<'_, A> {
+  fn deref_mut( &mut self ) -> &mut Neighbor {
+    let last = self.queue.neighbors.len() - 1;
+    &mut self.queue.neighbors[last]
+  }
+}
+
+impl<A: Allocator> Drop for WorstGuard<'_, A> {
+  fn drop( &mut self ) {
+    let last = self.queue.neighbors.len() - 1;
+    let worst = self.queue.neighbors[last];
+    match self.queue.tie_break.compare( &worst, &self.original ) {
+      Ordering::Less => {
+        _ = self.queue.neighbors.pop();
+        self.queue.insert( worst );
+      }
+      Ordering::Equal => {}
+      Ordering::Greater => {
+        _ = self.queue.neighbors.pop();
+      }
+    }
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// queue bounded by both `capacity` neighbors and `budget` bytes of payload, as measured by `size_of`. After each insert the
+// farthest neighbors are evicted until the total fits the budget, which may evict the inserted neighbor itself.
+pub struct ByteBoundedQueue {
+  queue: Queue,
+  size_of: fn( &Neighbor ) -> usize,
+  budget: usize,
+  bytes: usize,
+}
+
+impl ByteBoundedQueue {
+  pub fn with_capacity( capacity: NonZeroUsize, budget: usize, size_of: fn( &Neighbor ) -> usize ) -> Self {
+    Self { queue: Queue::with_capacity( capacity ), size_of, budget, bytes: 0 }
+  }
+
+  pub fn as_slice( &self ) -> &[Neighbor] {
+    self.queue.as_slice()
+  }
+
+  pub fn len( &self ) -> usize {
+    self.queue.len()
+  }
+
+  pub fn is_empty( &self ) -> bool {
+    self.queue.is_empty()
+  }
+
+  pub fn capacity( &self ) -> NonZeroUsize {
+    self.queue.capacity()
+  }
+
+  pub fn budget( &self ) -> usize {
+    self.budget
+  }
+
+  // payload bytes of the queued neighbors, at most `budget`
+  pub fn bytes( &self ) -> usize {
+    self.bytes
+  }
+
+  pub fn insert( &mut self, neighbor: Neighbor ) {
+    match self.queue.insert_evicting( neighbor ) {
+      Insertion::Rejected => return,
+      Insertion::Inserted( _ ) => {}
+      Insertion::Evicted( _, evicted ) => self.bytes -= (self.size_of)( &evicted ),
+    }
+    self.bytes += (self.size_of)( &neighbor );
+    while self.bytes > self.budget && let Some( worst ) = self.queue.neighbors.pop() {
+      self.bytes -= (self.size_of)( &worst );
+    }
+  }
+
+  pub fn clear( &mut self ) {
+    self.queue.clear();
+    self.bytes = 0;
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// queue recording the shard each neighbor came from, e.g. to route a merged result back to its shards. The shards are a
+// parallel buffer kept in the order of the neighbors, so that a neighbor and its shard are always evicted together.
+pub struct TaggedQueue {
+  queue: Queue,
+  shards: Vec<Option<u16>>,
+}
+
+impl TaggedQueue {
+  pub fn with_capacity( capacity: NonZeroUsize ) -> Self {
+    Self { queue: Queue::with_capacity( capacity ), shards: Vec::with_capacity( capacity.get() ) }
+  }
+
+  pub fn as_slice( &self ) -> &[Neighbor] {
+    self.queue.as_slice()
+  }
+
+  // shard of each neighbor of `as_slice`, None for the untagged ones
+  pub fn shards( &self ) -> &[Option<u16>] {
+    &self.shards
+  }
+
+  pub fn len( &self ) -> usize {
+    self.queue.len()
+  }
+
+  pub fn is_empty( &self ) -> bool {
+    self.queue.is_empty()
+  }
+
+  pub fn capacity( &self ) -> NonZeroUsize {
+    self.queue.capacity()
+  }
+
+  // shard of the nearest queued neighbor with this id
+  pub fn shard_of( &self, id: u32 ) -> Option<u16> {
+    let pos = self.queue.neighbors.iter().position( |neighbor| neighbor.id == id )?;
+    self.shards[pos]
+  }
+
+  pub fn insert( &mut self, neighbor: Neighbor ) {
+    self.insert_with_shard( neighbor, None );
+  }
+
+  pub fn insert_tagged( &mut self, neighbor: Neighbor, shard: u16 ) {
+    self.insert_with_shard( neighbor, Some( shard ) );
+  }
+
+  // inserts every neighbor of `other` tagged with `shard`
+  pub fn merge_tagged( &mut self, other: &Queue, shard: u16 ) {
+    for neighbor in other.as_slice() {
+      self.insert_tagged( *neighbor, shard );
+    }
+  }
+
+  fn insert_with_shard( &mut self, neighbor: Neighbor, shard: Option<u16> ) {
+    match self.queue.insert_evicting( neighbor ) {
+      Insertion::Rejected => {}
+      Insertion::Inserted( pos ) => self.shards.insert( pos, shard ),
+      Insertion::Evicted( pos, _ ) => {
+        _ = self.shards.pop();
+        self.shards.insert( pos, shard );
+      }
+    }
+  }
+
+  pub fn clear( &mut self ) {
+    self.queue.clear();
+    self.shards.clear();
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// unsorted input, the allocation of the `Vec` is reused. It is sorted with the total order of `Ord for Neighbor`, the queue
+// order but for NaN distances, which `total_cmp` puts after +inf.
+impl From<(NonZeroUsize, Vec<Neighbor>)> for Queue {
+  fn from( (capacity, mut neighbors): (NonZeroUsize, Vec<Neighbor>) ) -> Self {
+    neighbors.sort_unstable();
+    neighbors.dedup();
+    neighbors.truncate( capacity.get() );
+    neighbors.reserve_exact( capacity.get() - neighbors.len() );
+    Self::from_parts( buffer::from_vec( neighbors ), capacity )
+  }
+}
+
+// reserves once for the lower size hint up to the capacity (only an unbounded queue can be short of room), then inserts
+impl<A: Allocator> Extend<Neighbor> for Queue<A> {
+  fn extend<I: IntoIterator<Item = Neighbor>>( &mut self, iter: I ) {
+    let iter = iter.into_iter();
+    let room = self.capacity.get() - self.neighbors.len();
+    self.neighbors.reserve( iter.size_hint().0.min( room ) );
+    for neighbor in iter {
+      self.insert( neighbor );
+    }
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// k-way merge of the sorted buffers of `queues` into a new queue, same result as inserting every neighbor of every queue.
+// The queues are expected to use the default `TieBreak::Id`.
+pub fn merge_k<A: Allocator>( queues: &[Queue<A>], capacity: NonZeroUsize ) -> Queue {
+  let mut merged = Vec::with_capacity( capacity.get() );
+  merge_sorted_slices( queues.iter().map( Queue::as_slice ), capacity, &mut merged );
+  Queue::from_parts( buffer::from_vec( merged ), capacity )
+}
+
+// stateless sibling of `merge_k` for shards sorted with `neighbor_cmp`: their `k` nearest neighbors
+pub fn merge_sorted_vecs( shards: &[Vec<Neighbor>], k: NonZeroUsize ) -> Vec<Neighbor> {
+  let total = shards.iter().map( Vec::len ).sum::<usize>();
+  let mut merged = Vec::with_capacity( total.min( k.get() ) );
+  merge_sorted_slices( shards.iter().map( Vec::as_slice ), k, &mut merged );
+  merged
+}
+
+fn merge_sorted_slices<'a>( slices: impl Iterator<Item = &'a [Neighbor]>, k: NonZeroUsize, merged: &mut Vec<Neighbor> ) {
+  let mut heads = slices
+    .filter( |neighbors| !neighbors.is_empty() )
+    .map( Head )
+    .collect::<BinaryHeap<_>>();
+
+  while merged.len() < k.get() && let Some( mut head ) = heads.peek_mut() {
+    let neighbor = head.0[0];
+    // exact duplicates are dropped as `insert` would do
+    if merged.last().is_none_or( |last| neighbor_cmp( last, &neighbor ) != Ordering::Equal ) {
+      merged.push( neighbor );
+    }
+    if head.0.len() == 1 {
+      _ = PeekMut::pop( head );
+    } else {
+      head.0 = &head.0[1..];
+    }
+  }
+}
+
+// remaining part of a sorted buffer, ordered on its first neighbor so that `BinaryHeap` pops the nearest one first
+struct Head<'a>( &'a [Neighbor] );
+
+impl Ord for Head<'_> {
+  fn cmp( &self, other: &Self ) -> Ordering {
+    neighbor_cmp( &other.0[0], &self.0[0] )
+  }
+}
+
+impl PartialOrd for Head<'_> {
+  fn partial_cmp( &self, other: &Self ) -> Option<Ordering> {
+    Some( self.cmp( other ) )
+  }
+}
+
+impl PartialEq for Head<'_> {
+  fn eq( &self, other: &Self ) -> bool {
+    self.cmp( other ) == Ordering::Equal
+  }
+}
+
+impl Eq for Head<'_> {}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// positions in `items` of its `k` nearest neighbors, in the queue order. Unlike a queue, neighbors equal on (dist, id)
+// are not deduplicated since their positions differ. The selection uses the total order of `Ord for Neighbor`, so NaN
+// distances rank after +inf.
+pub fn top_k_indices( items: &[Neighbor], k: NonZeroUsize ) -> Vec<usize> {
+  let cmp = |a: &usize, b: &usize| items[*a].cmp( &items[*b] ).then( a.cmp( b ) );
+  let mut indices = (0..items.len()).collect::<Vec<_>>();
+  if k.get() < indices.len() {
+    indices.select_nth_unstable_by( k.get() - 1, cmp );
+    indices.truncate( k.get() );
+  }
+  indices.sort_unstable_by( cmp );
+  indices
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// brute-force check of a search pipeline: ids of the true `k` nearest `(id, dist)` of `points` missing from `produced`, in
+// the queue order. Ties on the k-th distance are resolved by id, as the queue does.
+pub fn verify_top_k<A: Allocator>( produced: &Queue<A>, points: impl Iterator<Item = (u32, f32)>, k: NonZeroUsize ) -> Vec<u32> {
+  let mut truth = Queue::with_capacity( k );
+  for (id, dist) in points {
+    truth.insert( Neighbor::new( id, dist ) );
+  }
+  let produced = produced.as_slice().iter().map( |neighbor| neighbor.id ).collect::<HashSet<_>>();
+  truth.as_slice().iter().map( |neighbor| neighbor.id ).filter( |id| !produced.contains( id ) ).collect()
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use rand::Rng;
+  use crate::test_util::{bits, grid_neighbor, rng};
+
+  fn nz( n: usize ) -> NonZeroUsize {
+    NonZeroUsize::new( n ).unwrap()
+  }
+
+  fn queue_of( capacity: usize, neighbors: &[(u32, f32)] ) -> Queue {
+    let mut queue = Queue::with_capacity( nz( capacity ) );
+    for &(id, dist) in neighbors {
+      queue.insert( Neighbor::new( id, dist ) );
+    }
+    queue
+  }
+
+  fn ids( neighbors: &[Neighbor] ) -> Vec<u32> {
+    neighbors.iter().map( |neighbor| neighbor.id ).collect()
+  }
+
+  #[test]
+  fn insert_hinted_matches_insert() {
+    let mut rng = rng();
+    for _ in 0..200 {
+      let capacity = nz( rng.random_range( 1..12 ) );
+      let mut hinted = Queue::with_capacity( capacity );
+      let mut queue = Queue::with_capacity( capacity );
+      let mut hint = 0;
+      for _ in 0..40 {
+        let neighbor = grid_neighbor( &mut rng );
+        // mostly the returned position, sometimes anywhere up to well past the length
+        if rng.random_bool( 0.3 ) {
+          hint = rng.random_range( 0..2 * capacity.get() + 2 );
+        }
+        hint = hinted.insert_hinted( neighbor, hint );
+        queue.insert( neighbor );
+        assert_eq!( bits( hinted.as_slice() ), bits( queue.as_slice() ) );
+      }
+    }
+  }
+
+  #[test]
+  fn insert_id_dist_matches_insert() {
+    let mut rng = rng();
+    for _ in 0..200 {
+      let capacity = nz( rng.random_range( 1..12 ) );
+      let mut scalars = Queue::with_capacity( capacity );
+      let mut queue = Queue::with_capacity( capacity );
+      for _ in 0..40 {
+        let neighbor = grid_neighbor( &mut rng );
+        scalars.insert_id_dist( neighbor.id, neighbor.dist );
+        queue.insert( neighbor );
+        assert_eq!( bits( scalars.as_slice() ), bits( queue.as_slice() ) );
+      }
+    }
+  }
+
+  #[test]
+  fn try_reject_only_rejects_what_insert_rejects() {
+    // not full: nothing is rejected, however far
+    let mut queue = queue_of( 3, &[ (1, 0.5), (5, 1.0) ] );
+    assert!( !queue.try_reject( f32::INFINITY ) );
+
+    queue.insert( Neighbor::new( 3, 0.75 ) );
+    assert!( queue.try_reject( 1.5 ) );
+    // the distance of the worst with a smaller id is accepted by `insert`, so it must not be rejected
+    assert!( !queue.try_reject( 1.0 ) );
+    queue.insert( Neighbor::new( 2, 1.0 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 3, 2 ] );
+    // NaN is never greater than the worst, it is left to `insert`
+    assert!( !queue.try_reject( f32::NAN ) );
+    let mut plain = queue_of( 3, &[ (1, 0.5), (3, 0.75), (2, 1.0) ] );
+    queue.insert( Neighbor::new( 4, f32::NAN ) );
+    plain.insert( Neighbor::new( 4, f32::NAN ) );
+    assert_eq!( bits( queue.as_slice() ), bits( plain.as_slice() ) );
+  }
+
+  #[test]
+  fn try_reject_then_insert_matches_insert() {
+    let mut rng = rng();
+    for _ in 0..200 {
+      let capacity = nz( rng.random_range( 1..12 ) );
+      let mut gated = Queue::with_capacity( capacity );
+      let mut queue = Queue::with_capacity( capacity );
+      for _ in 0..40 {
+        let neighbor = grid_neighbor( &mut rng );
+        if !gated.try_reject( neighbor.dist ) {
+          gated.insert( neighbor );
+        }
+        queue.insert( neighbor );
+        assert_eq!( bits( gated.as_slice() ), bits( queue.as_slice() ) );
+      }
+    }
+  }
+
+  #[test]
+  fn insert_hinted_returns_the_position() {
+    let mut queue = queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] );
+    assert_eq!( queue.insert_hinted( Neighbor::new( 4, 0.2 ), 100 ), 2 );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2, 4, 3 ] );
+    // a duplicate is rejected at the position it would take
+    assert_eq!( queue.insert_hinted( Neighbor::new( 2, 0.2 ), 0 ), 1 );
+    // past the capacity
+    assert_eq!( queue.insert_hinted( Neighbor::new( 5, 0.4 ), 2 ), 4 );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2, 4, 3 ] );
+  }
+
+  #[test]
+  fn merge_k_matches_inserting_every_shard() {
+    let mut rng = rng();
+    for _ in 0..100 {
+      let capacity = nz( rng.random_range( 1..16 ) );
+      let shards = (0..8).map( |_| {
+        let mut shard = Queue::with_capacity( capacity );
+        for _ in 0..rng.random_range( 0..20 ) {
+          shard.insert( grid_neighbor( &mut rng ) );
+        }
+        shard
+      }).collect::<Vec<_>>();
+
+      let mut folded = Queue::with_capacity( capacity );
+      for shard in &shards {
+        for neighbor in shard.as_slice() {
+          folded.insert( *neighbor );
+        }
+      }
+      assert_eq!( bits( merge_k( &shards, capacity ).as_slice() ), bits( folded.as_slice() ) );
+    }
+  }
+
+  #[test]
+  fn merge_k_drops_duplicates_across_shards() {
+    let shards = [
+      queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] ),
+      queue_of( 4, &[ (1, 0.1), (2, 0.25), (4, 0.3) ] ),
+      queue_of( 4, &[ (1, 0.1), (3, 0.3) ] ),
+    ];
+    let merged = merge_k( &shards, nz( 5 ) );
+    assert_eq!( bits( merged.as_slice() ), bits( &[
+      Neighbor::new( 1, 0.1 ), Neighbor::new( 2, 0.2 ), Neighbor::new( 2, 0.25 ), Neighbor::new( 3, 0.3 ), Neighbor::new( 4, 0.3 ),
+    ] ) );
+  }
+
+  #[test]
+  fn insert_or_replace_upserts_on_id() {
+    let mut queue = queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] );
+    assert_eq!( queue.insert_or_replace( Neighbor::new( 4, 0.25 ) ), None );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2, 4, 3 ] );
+
+    // a larger distance is taken all the same and moves the neighbor back
+    assert_eq!( queue.insert_or_replace( Neighbor::new( 1, 0.5 ) ), Some( 0.1 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 2, 4, 3, 1 ] );
+    assert_eq!( queue.kth_dist( 4 ), Some( 0.5 ) );
+
+    // a smaller one moves it forward
+    assert_eq!( queue.insert_or_replace( Neighbor::new( 3, 0.0 ) ), Some( 0.3 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 3, 2, 4, 1 ] );
+    assert_eq!( queue.len(), 4 );
+  }
+
+  #[test]
+  fn capacity_utilization_is_len_over_capacity() {
+    let mut queue = Queue::with_capacity( nz( 4 ) );
+    assert_eq!( queue.capacity_utilization(), 0.0 );
+    queue.insert( Neighbor::new( 1, 0.1 ) );
+    assert_eq!( queue.capacity_utilization(), 0.25 );
+    for id in 2..10 {
+      queue.insert( Neighbor::new( id, id as f32 ) );
+    }
+    assert_eq!( queue.capacity_utilization(), 1.0 );
+  }
+
+  #[cfg(feature = "metrics")]
+  #[test]
+  fn is_saturated_since_counts_the_rejects_of_the_full_queue() {
+    let mut queue = queue_of( 2, &[ (1, 0.1) ] );
+    // rejects of a queue that is not full do not count
+    queue.insert( Neighbor::new( 1, 0.1 ) );
+    assert!( !queue.is_saturated_since( 0 ) );
+
+    queue.insert( Neighbor::new( 2, 0.2 ) );
+    assert!( queue.is_saturated_since( 0 ) );
+    assert!( !queue.is_saturated_since( 1 ) );
+    queue.insert( Neighbor::new( 3, 0.3 ) );
+    queue.insert( Neighbor::new( 4, 0.4 ) );
+    assert!( queue.is_saturated_since( 2 ) );
+    assert!( !queue.is_saturated_since( 3 ) );
+
+    // an accepted insert restarts the count
+    queue.insert( Neighbor::new( 5, 0.0 ) );
+    assert!( !queue.is_saturated_since( 1 ) );
+    queue.insert( Neighbor::new( 6, 0.5 ) );
+    assert!( queue.is_saturated_since( 1 ) );
+    queue.clear();
+    assert!( !queue.is_saturated_since( 0 ) );
+  }
+
+  #[cfg(feature = "allocator-api2")]
+  #[test]
+  fn with_capacity_in_allocates_through_the_allocator() {
+    use std::alloc::Layout;
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+    use allocator_api2::alloc::AllocError;
+
+    // forwards to `Global`, counting the allocations
+    #[derive(Clone, Copy)]
+    struct Counting<'a>( &'a Cell<usize> );
+
+    unsafe impl Allocator for Counting<'_> {
+      fn allocate( &self, layout: Layout ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.set( self.0.get() + 1 );
+        Global.allocate( layout )
+      }
+
+      unsafe fn deallocate( &self, ptr: NonNull<u8>, layout: Layout ) {
+        unsafe { Global.deallocate( ptr, layout ) }
+      }
+    }
+
+    let allocations = Cell::new( 0 );
+    let mut queue = Queue::with_capacity_in( nz( 4 ), Counting( &allocations ) );
+    let mut expected = Queue::with_capacity( nz( 4 ) );
+    assert_eq!( allocations.get(), 1 );
+    for id in 0..20 {
+      let neighbor = Neighbor::new( id, (id * 7 % 11) as f32 );
+      queue.insert( neighbor );
+      expected.insert( neighbor );
+    }
+    assert_eq!( allocations.get(), 1 );
+    assert_eq!( bits( queue.as_slice() ), bits( expected.as_slice() ) );
+
+    // growing reallocates through it too
+    queue.set_capacity( nz( 64 ) );
+    assert_eq!( allocations.get(), 2 );
+    queue.merge_sorted_dedup( &[ Neighbor::new( 30, 0.5 ) ] );
+    assert_eq!( allocations.get(), 3 );
+    assert_eq!( ids( queue.as_slice() ), [ 0, 11, 30, 8, 19 ] );
+  }
+
+  #[test]
+  fn to_id_sorted_vec_leaves_the_queue_sorted_by_distance() {
+    let queue = queue_of( 8, &[ (5, 0.1), (2, 0.4), (9, 0.2), (1, 0.3) ] );
+    assert_eq!( ids( &queue.to_id_sorted_vec() ), [ 1, 2, 5, 9 ] );
+    assert_eq!( ids( queue.as_slice() ), [ 5, 9, 1, 2 ] );
+  }
+
+  #[test]
+  fn insert_with_bound_stops_once_the_bound_cannot_beat_the_worst() {
+    let mut queue = Queue::with_capacity( nz( 2 ) );
+    // never stops before the queue is full
+    assert_eq!( queue.insert_with_bound( Neighbor::new( 1, 0.3 ), 10.0 ), InsertControl::Continue );
+    assert_eq!( queue.insert_with_bound( Neighbor::new( 2, 0.5 ), 0.4 ), InsertControl::Continue );
+    assert_eq!( queue.insert_with_bound( Neighbor::new( 3, 0.2 ), 0.4 ), InsertControl::Stop );
+    assert_eq!( ids( queue.as_slice() ), [ 3, 1 ] );
+
+    // a bound equal to the worst distance cannot beat it either
+    let mut queue = queue_of( 2, &[ (1, 0.1) ] );
+    assert_eq!( queue.insert_with_bound( Neighbor::new( 2, 0.3 ), 0.2 ), InsertControl::Continue );
+    assert_eq!( queue.insert_with_bound( Neighbor::new( 3, 0.6 ), 0.3 ), InsertControl::Stop );
+  }
+
+  #[test]
+  fn resort_restores_the_order_after_swap_removes() {
+    let mut rng = rng();
+    for tie_break in [ TieBreak::Id, TieBreak::Random( 7 ) ] {
+      let mut queue = Queue::with_tie_break( nz( 32 ), tie_break );
+      let mut expected = Queue::with_tie_break( nz( 32 ), tie_break );
+      for _ in 0..64 {
+        let neighbor = grid_neighbor( &mut rng );
+        queue.insert( neighbor );
+        expected.insert( neighbor );
+      }
+
+      let mut removed = Vec::new();
+      for _ in 0..5 {
+        let index = rng.random_range( 0..queue.len() );
+        removed.push( queue.swap_remove( index ) );
+      }
+      queue.resort();
+      assert!( queue.is_sorted() );
+
+      let removed = bits( &removed );
+      let mut expected = bits( expected.as_slice() );
+      expected.retain( |neighbor| !removed.contains( neighbor ) );
+      assert_eq!( bits( queue.as_slice() ), expected );
+    }
+  }
+
+  #[test]
+  fn resort_orders_nan_distances_last() {
+    let mut rng = rng();
+    let mut queue = Queue::with_capacity( nz( 64 ) );
+    for id in 0..64 {
+      let dist = if rng.random_bool( 0.1 ) { f32::NAN } else { rng.random_range( 0..8 ) as f32 };
+      queue.insert( Neighbor::new( id, dist ) );
+    }
+    _ = queue.swap_remove( 0 );
+    queue.resort();
+    let nans = queue.as_slice().iter().filter( |neighbor| neighbor.dist.is_nan() ).count();
+    let (numbers, nans) = queue.as_slice().split_at( queue.len() - nans );
+    assert!( numbers.is_sorted_by( |a, b| neighbor_cmp( a, b ) != Ordering::Greater ) );
+    assert!( nans.iter().all( |neighbor| neighbor.dist.is_nan() ) );
+  }
+
+  #[test]
+  fn from_vec_matches_inserting_every_neighbor() {
+    let mut rng = rng();
+    for _ in 0..100 {
+      let capacity = nz( rng.random_range( 1..16 ) );
+      let neighbors = (0..rng.random_range( 0..40 )).map( |_| grid_neighbor( &mut rng ) ).collect::<Vec<_>>();
+      let mut expected = Queue::with_capacity( capacity );
+      expected.extend( neighbors.iter().copied() );
+      let queue = Queue::from( (capacity, neighbors) );
+      assert_eq!( bits( queue.as_slice() ), bits( expected.as_slice() ) );
+      assert!( queue.allocated_capacity() >= capacity.get() );
+    }
+  }
+
+  #[test]
+  fn from_vec_sorts_nan_distances_last() {
+    let mut rng = rng();
+    for _ in 0..100 {
+      let neighbors = (0..64).map( |id| {
+        let dist = if rng.random_bool( 0.1 ) { f32::NAN } else { rng.random_range( 0.0..1.0 ) };
+        Neighbor::new( id, dist )
+      }).collect::<Vec<_>>();
+      let numbers = neighbors.iter().filter( |neighbor| !neighbor.dist.is_nan() ).count();
+
+      let queue = Queue::from( (nz( 64 ), neighbors.clone()) );
+      assert_eq!( queue.len(), 64 );
+      assert!( queue.as_slice()[numbers..].iter().all( |neighbor| neighbor.dist.is_nan() ) );
+
+      // the nearest ones are kept as `insert` would
+      let capacity = nz( numbers.min( 16 ) );
+      let mut expected = Queue::with_capacity( capacity );
+      expected.extend( neighbors.iter().copied().filter( |neighbor| !neighbor.dist.is_nan() ) );
+      assert_eq!( bits( Queue::from( (capacity, neighbors) ).as_slice() ), bits( expected.as_slice() ) );
+    }
+  }
+
+  #[test]
+  fn drain_into_appends_in_order_and_keeps_the_allocation() {
+    let mut queue = queue_of( 4, &[ (3, 0.3), (1, 0.1), (2, 0.2) ] );
+    let allocated = queue.allocated_capacity();
+    let mut out = vec![ Neighbor::new( 9, 9.0 ) ];
+    queue.drain_into( &mut out );
+    assert_eq!( ids( &out ), [ 9, 1, 2, 3 ] );
+    assert!( queue.is_empty() );
+    assert_eq!( queue.allocated_capacity(), allocated );
+    assert_eq!( queue.capacity(), nz( 4 ) );
+  }
+
+  #[test]
+  fn as_slice_unchecked_matches_as_slice() {
+    let queue = queue_of( 8, &[ (3, 0.3), (1, 0.1), (2, 0.2), (4, 0.4) ] );
+    for len in 0..=queue.len() {
+      assert_eq!( bits( unsafe { queue.as_slice_unchecked( len ) } ), bits( &queue.as_slice()[..len] ) );
+    }
+  }
+
+  #[test]
+  fn approx_eq_tolerates_distances_within_epsilon() {
+    let queue = queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] );
+    assert!( queue.approx_eq( &queue_of( 4, &[ (1, 0.1005), (2, 0.1995), (3, 0.3) ] ), 1e-3 ) );
+    assert!( !queue.approx_eq( &queue_of( 4, &[ (1, 0.1), (2, 0.21), (3, 0.3) ] ), 1e-3 ) );
+    // same distances but other ids, ranks or lengths
+    assert!( !queue.approx_eq( &queue_of( 4, &[ (1, 0.1), (4, 0.2), (3, 0.3) ] ), 1e-3 ) );
+    assert!( !queue.approx_eq( &queue_of( 4, &[ (2, 0.1), (1, 0.2), (3, 0.3) ] ), 1e-3 ) );
+    assert!( !queue.approx_eq( &queue_of( 4, &[ (1, 0.1), (2, 0.2) ] ), 1e-3 ) );
+  }
+
+  #[test]
+  fn insert_weighted_ranks_and_evicts_on_the_weighted_distance() {
+    let mut queue = Queue::with_capacity( nz( 2 ) );
+    queue.insert_weighted( 1, 1.0, 0.5 );
+    queue.insert_weighted( 2, 0.4, 2.0 );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2 ] );
+    assert_eq!( dists( queue.as_slice() ), [ 0.5, 0.8 ] );
+
+    // a raw distance below the worst one that weighs more is rejected
+    queue.insert_weighted( 3, 0.3, 3.0 );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2 ] );
+    // a raw distance above it that weighs less evicts it
+    queue.insert_weighted( 4, 2.0, 0.1 );
+    assert_eq!( ids( queue.as_slice() ), [ 4, 1 ] );
+  }
+
+  #[test]
+  fn merge_sorted_dedup_keeps_the_best_of_each_id() {
+    let mut queue = queue_of( 4, &[ (1, 0.1), (2, 0.4), (3, 0.5) ] );
+    queue.merge_sorted_dedup( &[ Neighbor::new( 2, 0.2 ), Neighbor::new( 1, 0.3 ), Neighbor::new( 4, 0.45 ), Neighbor::new( 5, 0.6 ) ] );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2, 4, 3 ] );
+    assert_eq!( dists( queue.as_slice() ), [ 0.1, 0.2, 0.45, 0.5 ] );
+  }
+
+  // run with and without `cmov-compare`: the comparator of either build must give the order of both, which only holds
+  // for non-NaN distances (the branchy one has no order for NaN, `total_cmp` sorts it at the ends)
+  #[test]
+  fn both_comparators_order_non_nan_distances_alike() {
+    let dists = [ f32::NEG_INFINITY, -1.0, -0.0, 0.0, f32::MIN_POSITIVE / 2.0, 0.25, 1.0, f32::MAX, f32::INFINITY ];
+    let neighbors = dists.iter().flat_map( |&dist| (0..3).map( move |id| Neighbor::new( id, dist ) ) ).collect::<Vec<_>>();
+    for a in &neighbors {
+      for b in &neighbors {
+        let branchy = a.dist.partial_cmp( &b.dist ).unwrap().then( a.id.cmp( &b.id ) );
+        let total = (a.dist + 0.0).total_cmp( &(b.dist + 0.0) ).then( a.id.cmp( &b.id ) );
+        assert_eq!( neighbor_cmp( a, b ), branchy, "{a:?} {b:?}" );
+        assert_eq!( neighbor_cmp( a, b ), total, "{a:?} {b:?}" );
+      }
+    }
+
+    let mut rng = rng();
+    let mut sorted = (0..200).map( |_| Neighbor::new( rng.random_range( 0..8 ), dists[rng.random_range( 0..dists.len() )] ) )
+      .collect::<Vec<_>>();
+    let mut expected = sorted.clone();
+    sorted.sort_by( neighbor_cmp );
+    expected.sort();
+    assert_eq!( bits( &sorted ), bits( &expected ) );
+  }
+
+  #[test]
+  fn peek_worst_mut_moves_an_improved_worst_back_in_order() {
+    let mut queue = queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3), (4, 0.4) ] );
+    queue.peek_worst_mut().unwrap().dist = 0.15;
+    assert_eq!( ids( queue.as_slice() ), [ 1, 4, 2, 3 ] );
+    assert_eq!( dists( queue.as_slice() ), [ 0.1, 0.15, 0.2, 0.3 ] );
+    // the full queue stays full and the next reject is unchanged
+    queue.insert( Neighbor::new( 5, 0.35 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 4, 2, 3 ] );
+  }
+
+  #[test]
+  fn peek_worst_mut_evicts_a_worsened_worst() {
+    let mut queue = queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] );
+    queue.peek_worst_mut().unwrap().dist = 0.9;
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2 ] );
+    // on a tie, a larger id ranks it farther too
+    queue.peek_worst_mut().unwrap().id = 7;
+    assert_eq!( ids( queue.as_slice() ), [ 1 ] );
+  }
+
+  #[test]
+  fn peek_worst_mut_leaves_an_unchanged_worst_in_place() {
+    let mut queue = queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] );
+    {
+      let worst = queue.peek_worst_mut().unwrap();
+      assert_eq!( worst.id, 3 );
+    }
+    queue.peek_worst_mut().unwrap().dist = 0.3;
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2, 3 ] );
+    assert_eq!( dists( queue.as_slice() ), [ 0.1, 0.2, 0.3 ] );
+
+    assert!( Queue::with_capacity( nz( 4 ) ).peek_worst_mut().is_none() );
+  }
+
+  #[test]
+  fn into_boxed_slice_is_sized_to_the_results() {
+    let queue = queue_of( 16, &[ (3, 0.3), (1, 0.1), (2, 0.2) ] );
+    let boxed = queue.into_boxed_slice();
+    assert_eq!( boxed.len(), 3 );
+    assert_eq!( ids( &boxed ), [ 1, 2, 3 ] );
+  }
+
+  #[test]
+  fn clear_retaining_best_keeps_the_nearest_n() {
+    let mut queue = Queue::with_capacity( nz( 8 ) );
+    for id in 0..8 {
+      queue.insert( Neighbor::new( id, (8 - id) as f32 ) );
+    }
+    queue.clear_retaining_best( 5 );
+    assert_eq!( ids( queue.as_slice() ), [ 7, 6, 5, 4, 3 ] );
+    assert_eq!( queue.capacity(), nz( 8 ) );
+    // the freed slots take new neighbors without evicting the retained ones
+    queue.insert( Neighbor::new( 9, 9.0 ) );
+    assert_eq!( queue.len(), 6 );
+
+    queue.clear_retaining_best( 10 );
+    assert_eq!( queue.len(), 6 );
+  }
+
+  #[test]
+  fn accepts_anything_until_full_then_what_beats_the_worst() {
+    let mut queue = Queue::with_capacity( nz( 2 ) );
+    assert!( queue.accepts( f32::INFINITY ) );
+    queue.insert( Neighbor::new( 1, 0.5 ) );
+    assert!( queue.accepts( 10.0 ) );
+    queue.insert( Neighbor::new( 2, 0.3 ) );
+    assert!( queue.accepts( 0.4 ) );
+    assert!( !queue.accepts( 0.5 ) );
+    assert!( !queue.accepts( 0.6 ) );
+  }
+
+  #[test]
+  fn random_tie_break_shuffles_equal_distances_per_seed() {
+    let orders = [ 1, 2 ].map( |seed| {
+      let mut queue = Queue::with_tie_break( nz( 32 ), TieBreak::Random( seed ) );
+      for id in 0..16 {
+        queue.insert( Neighbor::new( id, 0.5 ) );
+        queue.insert( Neighbor::new( 100 + id, (id % 2) as f32 ) );
+      }
+      assert!( queue.is_sorted() );
+      ids( queue.as_slice() )
+    });
+    assert_ne!( orders[0], orders[1] );
+
+    let ties = |order: &[u32]| order.iter().copied().filter( |id| *id < 16 ).collect::<Vec<_>>();
+    let mut sets = [ ties( &orders[0] ), ties( &orders[1] ) ];
+    assert_ne!( sets[0], sets[1] );
+    sets.iter_mut().for_each( |set| set.sort_unstable() );
+    assert_eq!( sets[0], (0..16).collect::<Vec<_>>() );
+    assert_eq!( sets[0], sets[1] );
+    // the distances still come first: the ties at 0.5 sit between the ones at 0 and at 1
+    assert!( orders[0][..8].iter().all( |id| *id >= 100 ) && orders[0][8..24].iter().all( |id| *id < 16 ) );
+
+    // the same seed gives the same order
+    let mut queue = Queue::with_tie_break( nz( 32 ), TieBreak::Random( 1 ) );
+    for id in (0..16).rev() {
+      queue.insert( Neighbor::new( 100 + id, (id % 2) as f32 ) );
+      queue.insert( Neighbor::new( id, 0.5 ) );
+    }
+    assert_eq!( ids( queue.as_slice() ), orders[0] );
+  }
+
+  #[test]
+  fn prewarm_has_no_visible_effect() {
+    let mut queue = Queue::with_capacity( nz( 64 ) );
+    queue.prewarm();
+    assert!( queue.is_empty() );
+    queue.insert( Neighbor::new( 2, 0.2 ) );
+    queue.insert( Neighbor::new( 1, 0.1 ) );
+    queue.prewarm();
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2 ] );
+    assert_eq!( dists( queue.as_slice() ), [ 0.1, 0.2 ] );
+  }
+
+  #[test]
+  fn neighbor_cmp_sorts_inputs_for_the_sorted_merges() {
+    let mut rng = rng();
+    let mut sorted = (0..64).map( |_| grid_neighbor( &mut rng ) ).collect::<Vec<_>>();
+    sorted.sort_by( neighbor_cmp );
+
+    // the sorted input passes the debug assert of `merge_sorted_dedup` and matches plain inserts
+    let mut queue = Queue::with_capacity( nz( 16 ) );
+    queue.merge_sorted_dedup( &sorted );
+    let mut seen = HashSet::new();
+    let mut expected = Queue::with_capacity( nz( 16 ) );
+    expected.extend( sorted.iter().copied().filter( |neighbor| seen.insert( neighbor.id ) ) );
+    assert_eq!( bits( queue.as_slice() ), bits( expected.as_slice() ) );
+
+    let mut queue = Queue::with_capacity( nz( 16 ) );
+    queue.insert_sorted_run( sorted.iter().copied() );
+    let mut expected = Queue::with_capacity( nz( 16 ) );
+    expected.extend( sorted.iter().copied() );
+    assert_eq!( bits( queue.as_slice() ), bits( expected.as_slice() ) );
+  }
+
+  #[test]
+  fn with_capacity_power_of_two_is_still_bounded_by_the_capacity() {
+    let mut queue = Queue::with_capacity_power_of_two( nz( 10 ) );
+    assert_eq!( queue.allocated_capacity(), 16 );
+    for id in 0..20 {
+      queue.insert( Neighbor::new( id, (20 - id) as f32 ) );
+    }
+    assert_eq!( queue.len(), 10 );
+    assert_eq!( queue.capacity(), nz( 10 ) );
+    assert_eq!( ids( queue.as_slice() ), (10..20).rev().collect::<Vec<_>>() );
+  }
+
+  #[cfg(feature = "allocator-api2")]
+  #[test]
+  fn aligned32_aligns_the_buffer() {
+    use crate::buffer::Aligned32;
+
+    for capacity in [ 1, 3, 10, 64, 100 ] {
+      let mut queue = Queue::with_capacity_power_of_two_in( nz( capacity ), Aligned32( Global ) );
+      assert_eq!( queue.as_slice().as_ptr() as usize % 32, 0 );
+      for id in 0..200 {
+        queue.insert( Neighbor::new( id, (200 - id) as f32 ) );
+      }
+      assert_eq!( queue.len(), capacity );
+      // growing keeps the alignment
+      queue.set_capacity( nz( 4 * capacity ) );
+      assert_eq!( queue.as_slice().as_ptr() as usize % 32, 0 );
+    }
+  }
+
+  #[test]
+  fn diff_reports_the_ids_on_one_side_only() {
+    let ours = queue_of( 8, &[ (5, 0.1), (1, 0.2), (3, 0.3), (7, 0.4) ] );
+    let theirs = queue_of( 8, &[ (3, 0.1), (2, 0.2), (5, 0.5), (9, 0.6), (4, 0.7) ] );
+    assert_eq!( ours.diff( &theirs ), (vec![ 1, 7 ], vec![ 2, 4, 9 ]) );
+    assert_eq!( theirs.diff( &ours ), (vec![ 2, 4, 9 ], vec![ 1, 7 ]) );
+    assert_eq!( ours.diff( &ours ), (vec![], vec![]) );
+  }
+
+  #[test]
+  fn recall_against_is_the_fraction_of_truth_found() {
+    let queue = queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3), (4, 0.4) ] );
+    assert_eq!( queue.recall_against( &[ 4, 2, 1, 3 ] ), 1.0 );
+    assert_eq!( queue.recall_against( &[ 5, 6 ] ), 0.0 );
+    assert_eq!( queue.recall_against( &[ 1, 5, 3, 6 ] ), 0.5 );
+    assert_eq!( queue.recall_against( &[] ), 1.0 );
+  }
+
+  #[test]
+  fn capacity_bytes_follows_the_allocation() {
+    let mut queue = Queue::with_capacity( nz( 10 ) );
+    assert_eq!( queue.capacity_bytes(), queue.allocated_capacity() * size_of::<Neighbor>() );
+    assert!( queue.capacity_bytes() >= 10 * size_of::<Neighbor>() );
+    queue.reserve_exact( 100 );
+    assert_eq!( queue.capacity_bytes(), queue.allocated_capacity() * size_of::<Neighbor>() );
+    assert!( queue.capacity_bytes() >= 100 * size_of::<Neighbor>() );
+  }
+
+  #[test]
+  fn top_k_indices_map_back_to_the_nearest_neighbors() {
+    let mut rng = rng();
+    for _ in 0..100 {
+      let items = (0..rng.random_range( 0..40 )).map( |_| grid_neighbor( &mut rng ) ).collect::<Vec<_>>();
+      let k = nz( rng.random_range( 1..16 ) );
+      let indices = top_k_indices( &items, k );
+      assert_eq!( indices.len(), k.get().min( items.len() ) );
+
+      let mut expected = items.clone();
+      expected.sort_by( neighbor_cmp );
+      expected.truncate( k.get() );
+      let picked = indices.iter().map( |index| items[*index] ).collect::<Vec<_>>();
+      assert_eq!( bits( &picked ), bits( &expected ) );
+      // duplicates keep distinct positions
+      assert_eq!( indices.iter().collect::<HashSet<_>>().len(), indices.len() );
+    }
+  }
+
+  #[test]
+  fn top_k_indices_ranks_nan_distances_last() {
+    let mut rng = rng();
+    for _ in 0..100 {
+      let items = (0..64).map( |id| {
+        let dist = if rng.random_bool( 0.1 ) { f32::NAN } else { rng.random_range( 0.0..1.0 ) };
+        Neighbor::new( id, dist )
+      }).collect::<Vec<_>>();
+      let numbers = items.iter().filter( |neighbor| !neighbor.dist.is_nan() ).count();
+      let indices = top_k_indices( &items, nz( 64 ) );
+      assert!( indices[..numbers].iter().all( |index| !items[*index].dist.is_nan() ) );
+      assert!( indices[numbers..].iter().all( |index| items[*index].dist.is_nan() ) );
+    }
+  }
+
+  #[test]
+  fn merge_within_keeps_both_queues_within_the_radius() {
+    let mut queue = queue_of( 4, &[ (1, 0.1), (2, 0.5), (3, 0.9) ] );
+    let other = queue_of( 4, &[ (4, 0.2), (5, 0.5), (6, 0.7) ] );
+    queue.merge_within( &other, 0.5 );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 4, 2, 5 ] );
+
+    // bounded to the capacity
+    let mut queue = queue_of( 2, &[ (1, 0.1), (2, 0.3) ] );
+    queue.merge_within( &queue_of( 4, &[ (4, 0.2), (5, 0.25), (6, 0.9) ] ), 1.0 );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 4 ] );
+  }
+
+  #[test]
+  fn suggest_capacity_grows_with_the_recall_and_stays_at_least_1() {
+    for dataset_len in [ 0, 1, 2, 100, 1_000_000 ] {
+      let mut previous = NonZeroUsize::MIN;
+      for step in 0..=20 {
+        let capacity = Queue::suggest_capacity( dataset_len, step as f32 / 20.0 );
+        assert!( capacity >= previous );
+        assert!( capacity.get() <= dataset_len.max( 1 ) );
+        previous = capacity;
+      }
+    }
+    assert_eq!( Queue::suggest_capacity( 0, 0.9 ), NonZeroUsize::MIN );
+    assert_eq!( Queue::suggest_capacity( 1_000, f32::NAN ), Queue::suggest_capacity( 1_000, 0.0 ) );
+    assert_eq!( Queue::suggest_capacity( 1_000, 2.0 ), Queue::suggest_capacity( 1_000, 0.999 ) );
+  }
+
+  #[test]
+  fn dist_chunks_groups_the_sorted_distances() {
+    let queue = queue_of( 16, &(0..10).map( |id| (id, (10 - id) as f32) ).collect::<Vec<_>>() );
+    let (chunks, remainder) = queue.dist_chunks::<4>();
+    assert_eq!( chunks.len(), 2 );
+    assert_eq!( chunks.collect::<Vec<_>>(), [ [ 1.0, 2.0, 3.0, 4.0 ], [ 5.0, 6.0, 7.0, 8.0 ] ] );
+    assert_eq!( remainder.collect::<Vec<_>>(), [ 9.0, 10.0 ] );
+
+    let (chunks, remainder) = queue.dist_chunks::<16>();
+    assert_eq!( chunks.len(), 0 );
+    assert_eq!( remainder.len(), 10 );
+  }
+
+  #[test]
+  fn merge_sorted_iter_stops_at_the_first_reject_of_the_full_queue() {
+    let mut queue = queue_of( 4, &[ (1, 0.15), (2, 0.35) ] );
+    let sorted = (0..100).map( |id| Neighbor::new( 100 + id, id as f32 / 10.0 ) );
+    let mut consumed = 0;
+    queue.merge_sorted_iter( sorted.inspect( |_| consumed += 1 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 100, 101, 1, 102 ] );
+    // 0.2 evicts 0.35, 0.3 is rejected and nothing is pulled after it
+    assert_eq!( consumed, 4 );
+    #[cfg(feature = "metrics")]
+    assert!( queue.is_saturated_since( 1 ) );
+  }
+
+  #[test]
+  fn kth_dist_counts_from_1() {
+    let queue = queue_of( 8, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] );
+    assert_eq!( queue.kth_dist( 0 ), None );
+    assert_eq!( queue.kth_dist( 1 ), Some( 0.1 ) );
+    assert_eq!( queue.kth_dist( 3 ), Some( 0.3 ) );
+    assert_eq!( queue.kth_dist( 4 ), None );
+    assert_eq!( queue.kth_dist( 8 ), None );
+  }
+
+  #[test]
+  fn split_at_rank_keeps_both_halves_sorted() {
+    let neighbors = [ (4, 0.4), (1, 0.1), (5, 0.5), (2, 0.2), (3, 0.3) ];
+    for (k, nearest, rest) in [
+      (0, &[][..], &[ 1, 2, 3, 4, 5 ][..]),
+      (2, &[ 1, 2 ], &[ 3, 4, 5 ]),
+      (5, &[ 1, 2, 3, 4, 5 ], &[]),
+      (9, &[ 1, 2, 3, 4, 5 ], &[]),
+    ] {
+      let (a, b) = queue_of( 8, &neighbors ).split_at_rank( k );
+      assert_eq!( ids( &a ), nearest );
+      assert_eq!( ids( &b ), rest );
+    }
+  }
+
+  #[test]
+  fn from_heap_keeps_the_nearest() {
+    let mut rng = rng();
+    let neighbors = (0..1000).map( |id| Neighbor::new( id, rng.random_range( 0..200 ) as f32 ) ).collect::<Vec<_>>();
+    let queue = Queue::from_heap( nz( 64 ), neighbors.iter().copied().collect() );
+
+    let mut top_k = Queue::with_capacity( nz( 64 ) );
+    top_k.extend( neighbors.iter().copied() );
+    assert_eq!( bits( queue.as_slice() ), bits( top_k.as_slice() ) );
+    assert_eq!( queue.capacity(), nz( 64 ) );
+  }
+
+  #[test]
+  fn verify_top_k_reports_what_a_buggy_producer_missed() {
+    let points = (0..100u32).map( |id| (id, (id * 37 % 100) as f32) ).collect::<Vec<_>>();
+    let k = nz( 10 );
+
+    let mut correct = Queue::with_capacity( k );
+    correct.extend( points.iter().map( |&(id, dist)| Neighbor::new( id, dist ) ) );
+    assert!( verify_top_k( &correct, points.iter().copied(), k ).is_empty() );
+
+    // skips every odd id, so it misses the odd ids of the true top-k, in the queue order
+    let mut buggy = Queue::with_capacity( k );
+    buggy.extend( points.iter().filter( |(id, _)| id % 2 == 0 ).map( |&(id, dist)| Neighbor::new( id, dist ) ) );
+    let missing = ids( correct.as_slice() ).into_iter().filter( |id| id % 2 == 1 ).collect::<Vec<_>>();
+    assert!( !missing.is_empty() );
+    assert_eq!( verify_top_k( &buggy, points.iter().copied(), k ), missing );
+  }
+
+  #[test]
+  fn restore_rolls_back_to_the_snapshot() {
+    let mut queue = queue_of( 4, &[ (1, 0.1), (2, 0.3), (3, 0.5), (4, 0.7) ] );
+    let snapshot = queue.snapshot();
+    let allocated = queue.allocated_capacity();
+
+    // descend: evictions, a capacity change and a partial clear
+    queue.insert( Neighbor::new( 5, 0.0 ) );
+    queue.insert( Neighbor::new( 6, 0.2 ) );
+    queue.set_capacity( nz( 2 ) );
+    queue.clear_retaining_best( 1 );
+    queue.insert( Neighbor::new( 7, 0.05 ) );
+
+    queue.restore( &snapshot );
+    assert_eq!( bits( queue.as_slice() ), bits( &[
+      Neighbor::new( 1, 0.1 ), Neighbor::new( 2, 0.3 ), Neighbor::new( 3, 0.5 ), Neighbor::new( 4, 0.7 ),
+    ] ) );
+    assert_eq!( queue.capacity(), nz( 4 ) );
+    assert_eq!( queue.allocated_capacity(), allocated );
+    // and again from the same snapshot
+    queue.insert( Neighbor::new( 8, 0.0 ) );
+    queue.restore( &snapshot );
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2, 3, 4 ] );
+  }
+
+  #[test]
+  fn insert_sorted_run_appends_and_stops_at_the_first_reject() {
+    let mut queue = queue_of( 5, &[ (1, 0.25), (2, 0.45) ] );
+    let run = (0..100).map( |id| Neighbor::new( 100 + id, id as f32 / 10.0 ) );
+    let mut consumed = 0;
+    queue.insert_sorted_run( run.inspect( |_| consumed += 1 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 100, 101, 102, 1, 103 ] );
+    // 0.3 evicts 0.45, 0.4 is rejected and nothing is pulled after it
+    assert_eq!( consumed, 5 );
+
+    // same result as inserting the whole run
+    let mut rng = rng();
+    for _ in 0..100 {
+      let capacity = nz( rng.random_range( 1..12 ) );
+      let mut run = (0..40).map( |_| grid_neighbor( &mut rng ) ).collect::<Vec<_>>();
+      run.sort_by( neighbor_cmp );
+      let mut queue = Queue::with_capacity( capacity );
+      let mut expected = Queue::with_capacity( capacity );
+      for _ in 0..rng.random_range( 0..8 ) {
+        let neighbor = grid_neighbor( &mut rng );
+        queue.insert( neighbor );
+        expected.insert( neighbor );
+      }
+      queue.insert_sorted_run( run.iter().copied() );
+      expected.extend( run.iter().copied() );
+      assert_eq!( bits( queue.as_slice() ), bits( expected.as_slice() ) );
+    }
+  }
+
+  #[test]
+  fn stability_score_averages_the_overlap_at_each_depth() {
+    let queue = queue_of( 4, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] );
+    assert_eq!( queue.stability_score( &queue ), 1.0 );
+    assert_eq!( queue.stability_score( &queue_of( 4, &[ (4, 0.1), (5, 0.2), (6, 0.3) ] ) ), 0.0 );
+    assert_eq!( Queue::with_capacity( nz( 4 ) ).stability_score( &Queue::with_capacity( nz( 4 ) ) ), 1.0 );
+
+    // overlaps of 1/1, 1/2 and 2/3
+    let other = queue_of( 4, &[ (1, 0.1), (3, 0.2), (4, 0.3) ] );
+    let expected = (1.0 + 1.0 / 2.0 + 2.0 / 3.0) / 3.0;
+    assert!( (queue.stability_score( &other ) - expected).abs() < 1e-6 );
+    assert_eq!( queue.stability_score( &other ), other.stability_score( &queue ) );
+    // the same ids in another order score less than 1
+    let reordered = queue_of( 4, &[ (3, 0.1), (2, 0.2), (1, 0.3) ] );
+    assert!( queue.stability_score( &reordered ) < 1.0 );
+  }
+
+  #[test]
+  fn as_sorted_borrows_the_buffer() {
+    let queue = queue_of( 4, &[ (2, 0.2), (1, 0.1) ] );
+    let sorted = queue.as_sorted();
+    assert!( matches!( sorted, Cow::Borrowed( _ ) ) );
+    assert_eq!( sorted.as_ptr(), queue.as_slice().as_ptr() );
+    assert_eq!( ids( &sorted ), [ 1, 2 ] );
+  }
+
+  // a read-only algorithm written once against `QueueView`
+  fn summary( view: QueueView<'_> ) -> (Option<u32>, Option<u32>, Vec<u32>, usize, Option<f32>, usize) {
+    (
+      view.best().map( |neighbor| neighbor.id ),
+      view.peek_worst().map( |neighbor| neighbor.id ),
+      ids( view.range( 0.2, 0.4 ) ),
+      view.count_within( 0.3 ),
+      view.kth_dist( 2 ),
+      view.into_iter().count(),
+    )
+  }
+
+  #[test]
+  fn queue_view_reads_queues_and_slices_alike() {
+    let queue = queue_of( 8, &[ (4, 0.4), (1, 0.1), (3, 0.3), (2, 0.2), (5, 0.5) ] );
+    let mut slice = queue.as_slice().to_vec();
+    slice.sort_by( neighbor_cmp );
+    let expected = (Some( 1 ), Some( 5 ), vec![ 2, 3 ], 3, Some( 0.2 ), 5);
+    assert_eq!( summary( queue.view() ), expected );
+    assert_eq!( summary( QueueView::new( &slice ) ), expected );
+    assert_eq!( summary( QueueView::new( &[] ) ), (None, None, vec![], 0, None, 0) );
+    // an empty range
+    assert!( queue.view().range( 0.4, 0.2 ).is_empty() );
+  }
+
+  #[test]
+  fn try_with_capacity_reports_capacities_that_cannot_be_allocated() {
+    let capacity = nz( Queue::MAX_CAPACITY + 1 );
+    let error = Queue::try_with_capacity( capacity ).err().unwrap();
+    assert_eq!( error, CapacityError::TooLarge { capacity: capacity.get() } );
+    assert!( error.to_string().contains( &Queue::MAX_CAPACITY.to_string() ) );
+    assert!( matches!( Queue::try_with_capacity( NonZeroUsize::MAX ), Err( CapacityError::TooLarge { .. } ) ) );
+    // fits the layout but not the memory
+    assert!( matches!( Queue::try_with_capacity( nz( Queue::MAX_CAPACITY ) ), Err( CapacityError::Alloc( _ ) ) ) );
+
+    let queue = Queue::try_with_capacity( nz( 16 ) ).unwrap();
+    assert!( queue.allocated_capacity() >= 16 );
+  }
+
+  #[test]
+  fn insert_reporting_collects_the_evicted_ids_in_order() {
+    let mut queue = queue_of( 3, &[ (1, 0.1), (2, 0.2), (3, 0.3) ] );
+    let mut evicted = Vec::new();
+    for (id, dist) in [ (4, 0.05), (5, 0.5), (6, 0.15), (7, 0.0), (6, 0.15) ] {
+      queue.insert_reporting( Neighbor::new( id, dist ), &mut evicted );
+    }
+    assert_eq!( evicted, [ 3, 2, 6 ] );
+    assert_eq!( ids( queue.as_slice() ), [ 7, 4, 1 ] );
+
+    // nothing is evicted before the queue is full
+    let mut queue = Queue::with_capacity( nz( 4 ) );
+    let mut evicted = Vec::new();
+    queue.insert_reporting( Neighbor::new( 1, 0.1 ), &mut evicted );
+    assert!( evicted.is_empty() );
+  }
+
+  #[test]
+  fn is_sorted_spots_a_broken_order() {
+    let mut queue = queue_of( 8, &[ (3, 0.3), (1, 0.1), (4, 0.4), (2, 0.2) ] );
+    assert!( queue.is_sorted() );
+    assert!( Queue::with_capacity( nz( 8 ) ).is_sorted() );
+    // swap_remove is the way to break the order without unsafe code
+    _ = queue.swap_remove( 0 );
+    assert!( !queue.is_sorted() );
+    queue.resort();
+    assert!( queue.is_sorted() );
+  }
+
+  #[test]
+  fn merge_sorted_vecs_matches_concatenating_and_sorting() {
+    let mut rng = rng();
+    for _ in 0..100 {
+      let shards = (0..4).map( |_| {
+        let mut shard = (0..rng.random_range( 0..20 )).map( |_| grid_neighbor( &mut rng ) ).collect::<Vec<_>>();
+        shard.sort_by( neighbor_cmp );
+        shard.dedup_by( |a, b| neighbor_cmp( a, b ) == Ordering::Equal );
+        shard
+      }).collect::<Vec<_>>();
+      let k = nz( rng.random_range( 1..24 ) );
+
+      let mut expected = shards.concat();
+      expected.sort_by( neighbor_cmp );
+      expected.dedup_by( |a, b| neighbor_cmp( a, b ) == Ordering::Equal );
+      expected.truncate( k.get() );
+      assert_eq!( bits( &merge_sorted_vecs( &shards, k ) ), bits( &expected ) );
+    }
+  }
+
+  #[test]
+  fn signed_zeros_are_the_same_distance() {
+    let zeros = [ (3, -0.0), (1, 0.0), (2, -0.0), (1, -0.0), (3, 0.0) ];
+    let forward = queue_of( 8, &zeros );
+    let mut reversed = zeros;
+    reversed.reverse();
+    let backward = queue_of( 8, &reversed );
+    // ordered by id alone, and the same id at -0.0 and +0.0 is a duplicate
+    assert_eq!( ids( forward.as_slice() ), [ 1, 2, 3 ] );
+    assert_eq!( ids( backward.as_slice() ), [ 1, 2, 3 ] );
+
+    assert_eq!( Neighbor::new( 1, -0.0 ), Neighbor::new( 1, 0.0 ) );
+    assert_eq!( Neighbor::new( 1, -0.0 ).cmp( &Neighbor::new( 2, 0.0 ) ), Ordering::Less );
+    assert_eq!( Neighbor::new( 2, -0.0 ).cmp( &Neighbor::new( 1, 0.0 ) ), Ordering::Greater );
+    assert_eq!( neighbor_cmp( &Neighbor::new( 2, -0.0 ), &Neighbor::new( 1, 0.0 ) ), Ordering::Greater );
+    // still after the negative distances
+    assert_eq!( neighbor_cmp( &Neighbor::new( 2, -0.0 ), &Neighbor::new( 1, -f32::MIN_POSITIVE ) ), Ordering::Greater );
+  }
+
+  // the bytes of the whole allocation of `queue`, initialized or not. The pointer comes from the buffer itself and not from
+  // `as_slice`, which would only be valid for the `len` neighbors
+  unsafe fn backing_memory( queue: &Queue ) -> &[u8] {
+    let bytes = queue.allocated_capacity() * std::mem::size_of::<Neighbor>();
+    unsafe { std::slice::from_raw_parts( queue.neighbors.as_ptr().cast::<u8>(), bytes ) }
+  }
+
+  #[test]
+  fn clear_zeroed_overwrites_the_whole_allocation() {
+    let mut queue = queue_of( 16, &[ (7, 0.5), (u32::MAX, -3.0), (42, f32::INFINITY), (9, 1.25) ] );
+    // everything past the neighbors is written once, so that all of the allocation can be read
+    queue.clear_zeroed();
+    for id in 1..=16 {
+      queue.insert( Neighbor::new( id, id as f32 ) );
+    }
+    assert!( unsafe { backing_memory( &queue ) }.iter().any( |&byte| byte != 0 ) );
+
+    queue.clear_zeroed();
+    assert!( queue.is_empty() );
+    assert_eq!( queue.allocated_capacity(), 16 );
+    assert!( unsafe { backing_memory( &queue ) }.iter().all( |&byte| byte == 0 ) );
+  }
+
+  #[test]
+  fn dedup_keep_best_by_id_keeps_the_nearest_of_each_id() {
+    let mut queue = Queue::with_capacity( nz( 16 ) );
+    for &(id, dist) in &[ (4, 3.0), (1, 2.0), (4, 0.5), (2, 1.0), (1, 4.0), (4, 6.0), (3, 5.0), (2, 7.0) ] {
+      queue.insert( Neighbor::new( id, dist ) );
+    }
+    assert_eq!( queue.len(), 8 );
+
+    queue.dedup_keep_best_by_id();
+    assert_eq!( bits( queue.as_slice() ), bits( &[
+      Neighbor::new( 4, 0.5 ), Neighbor::new( 2, 1.0 ), Neighbor::new( 1, 2.0 ), Neighbor::new( 3, 5.0 ),
+    ]));
+    assert!( queue.is_sorted() );
+  }
+
+  #[test]
+  fn retain_best_per_group_keeps_the_nearest_of_each_group() {
+    // clusters of ten ids: 3, 7 and 5 share cluster 0, 12 and 18 cluster 1
+    let mut queue = queue_of( 16, &[ (7, 2.0), (12, 1.5), (3, 0.5), (18, 1.0), (25, 3.0), (5, 2.5), (31, 4.0) ] );
+    queue.retain_best_per_group( |neighbor| neighbor.id / 10 );
+    assert_eq!( bits( queue.as_slice() ), bits( &[
+      Neighbor::new( 3, 0.5 ), Neighbor::new( 18, 1.0 ), Neighbor::new( 25, 3.0 ), Neighbor::new( 31, 4.0 ),
+    ]));
+    assert!( queue.is_sorted() );
+
+    // one group for everything keeps only the nearest neighbor
+    queue.retain_best_per_group( |_| 0 );
+    assert_eq!( ids( queue.as_slice() ), [ 3 ] );
+  }
+
+  #[test]
+  fn try_reserve_grows_or_reports_the_failure() {
+    let mut queue = Queue::unbounded( Growth::Exact );
+    queue.insert( Neighbor::new( 1, 1.0 ) );
+    assert!( queue.try_reserve( 100 ).is_ok() );
+    assert!( queue.allocated_capacity() >= 101 );
+    assert_eq!( ids( queue.as_slice() ), [ 1 ] );
+
+    // a real out of memory cannot be provoked portably, but a size past `isize::MAX` bytes fails the same way: an `Err`
+    // instead of an abort, with the queue left as it was
+    let allocated = queue.allocated_capacity();
+    assert!( queue.try_reserve( usize::MAX ).is_err() );
+    assert_eq!( queue.allocated_capacity(), allocated );
+    assert_eq!( ids( queue.as_slice() ), [ 1 ] );
+  }
+
+  #[test]
+  fn tagged_queue_tracks_the_shard_of_two_merged_shards() {
+    let a = queue_of( 4, &[ (1, 0.1), (2, 0.4), (3, 0.6), (4, 0.8) ] );
+    let b = queue_of( 4, &[ (5, 0.2), (6, 0.3), (3, 0.5), (7, 0.9) ] );
+    let mut merged = TaggedQueue::with_capacity( nz( 4 ) );
+    merged.merge_tagged( &a, 0 );
+    merged.merge_tagged( &b, 1 );
+
+    assert_eq!( ids( merged.as_slice() ), [ 1, 5, 6, 2 ] );
+    assert_eq!( merged.shards(), [ Some( 0 ), Some( 1 ), Some( 1 ), Some( 0 ) ] );
+    assert_eq!( merged.shard_of( 2 ), Some( 0 ) );
+    assert_eq!( merged.shard_of( 6 ), Some( 1 ) );
+    // evicted from both shards
+    assert_eq!( merged.shard_of( 3 ), None );
+    assert_eq!( merged.shard_of( 4 ), None );
+
+    // an untagged neighbor that evicts a tagged one does not inherit its shard
+    merged.insert( Neighbor::new( 2, 0.0 ) );
+    assert_eq!( ids( merged.as_slice() ), [ 2, 1, 5, 6 ] );
+    assert_eq!( merged.shards(), [ None, Some( 0 ), Some( 1 ), Some( 1 ) ] );
+    assert_eq!( merged.shard_of( 2 ), None );
+
+    merged.clear();
+    assert!( merged.shards().is_empty() );
+    assert_eq!( merged.shard_of( 1 ), None );
+  }
+
+  fn dists( neighbors: &[Neighbor] ) -> Vec<f32> {
+    neighbors.iter().map( |neighbor| neighbor.dist ).collect()
+  }
+
+  #[test]
+  fn clamp_range_rejects_negative_and_nan_max() {
+    assert_eq!( ClampRange::new( -1.0 ), None );
+    assert_eq!( ClampRange::new( f32::NAN ), None );
+    assert_eq!( ClampRange::new( 0.0 ).map( ClampRange::max ), Some( 0.0 ) );
+    assert_eq!( ClampRange::new( f32::INFINITY ).map( ClampRange::max ), Some( f32::INFINITY ) );
+  }
+
+  #[test]
+  fn insert_clamped_bounds_the_distances() {
+    let range = ClampRange::new( 2.0 ).unwrap();
+    let mut queue = Queue::with_capacity( nz( 8 ) );
+    queue.insert_clamped( Neighbor::new( 1, 0.5 ), range );
+    queue.insert_clamped( Neighbor::new( 2, -3.0 ), range );
+    queue.insert_clamped( Neighbor::new( 3, f32::INFINITY ), range );
+    queue.insert_clamped( Neighbor::new( 4, f32::NAN ), range );
+    queue.insert_clamped( Neighbor::new( 5, f32::NEG_INFINITY ), range );
+    assert_eq!( ids( queue.as_slice() ), [ 2, 5, 1, 3, 4 ] );
+    assert_eq!( dists( queue.as_slice() ), [ 0.0, 0.0, 0.5, 2.0, 2.0 ] );
+
+    // an unbounded range only clamps the negative and NaN distances
+    let range = ClampRange::new( f32::INFINITY ).unwrap();
+    let mut queue = Queue::with_capacity( nz( 8 ) );
+    queue.insert_clamped( Neighbor::new( 1, f32::INFINITY ), range );
+    queue.insert_clamped( Neighbor::new( 2, f32::NAN ), range );
+    queue.insert_clamped( Neighbor::new( 3, -1.0 ), range );
+    queue.insert_clamped( Neighbor::new( 4, 7.5 ), range );
+    assert_eq!( ids( queue.as_slice() ), [ 3, 4, 1, 2 ] );
+    assert_eq!( dists( queue.as_slice() ), [ 0.0, 7.5, f32::INFINITY, f32::INFINITY ] );
+  }
+
+  // payload of `id` bytes
+  fn id_bytes( neighbor: &Neighbor ) -> usize {
+    neighbor.id as usize
+  }
+
+  #[test]
+  fn byte_bounded_queue_keeps_the_nearest_that_fit() {
+    let mut queue = ByteBoundedQueue::with_capacity( nz( 4 ), 10, id_bytes );
+    queue.insert( Neighbor::new( 4, 0.4 ) );
+    queue.insert( Neighbor::new( 3, 0.3 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 3, 4 ] );
+    assert_eq!( queue.bytes(), 7 );
+
+    // 3 + 4 + 5 is over the budget, the farthest neighbor goes
+    queue.insert( Neighbor::new( 5, 0.1 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 5, 3 ] );
+    assert_eq!( queue.bytes(), 8 );
+
+    // a farther neighbor that fits the remaining bytes gets in
+    queue.insert( Neighbor::new( 2, 0.5 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 5, 3, 2 ] );
+    assert_eq!( queue.bytes(), 10 );
+
+    // and is the first to go for a nearer one
+    queue.insert( Neighbor::new( 1, 0.35 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 5, 3, 1 ] );
+    assert_eq!( queue.bytes(), 9 );
+    queue.insert( Neighbor::new( 6, 0.0 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 6 ] );
+    assert_eq!( queue.bytes(), 6 );
+
+    queue.clear();
+    queue.insert( Neighbor::new( 2, 0.5 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 2 ] );
+  }
+
+  #[test]
+  fn byte_bounded_queue_outlives_an_oversized_payload() {
+    let mut queue = ByteBoundedQueue::with_capacity( nz( 8 ), 100, |neighbor| if neighbor.id == 0 { 1000 } else { 1 } );
+    queue.insert( Neighbor::new( 0, 0.01 ) );
+    assert!( queue.is_empty() );
+    assert_eq!( queue.bytes(), 0 );
+
+    // the evicted neighbor leaves no trace, farther ones that fit are all kept
+    for id in 1..=7 {
+      queue.insert( Neighbor::new( id, id as f32 ) );
+    }
+    assert_eq!( ids( queue.as_slice() ), [ 1, 2, 3, 4, 5, 6, 7 ] );
+    assert_eq!( queue.bytes(), 7 );
+  }
+
+  #[test]
+  fn byte_bounded_queue_evicts_the_farthest_until_under_budget() {
+    let mut rng = rng();
+    for _ in 0..200 {
+      let (capacity, budget) = (rng.random_range( 1..8 ), rng.random_range( 0..40 ));
+      let mut queue = ByteBoundedQueue::with_capacity( nz( capacity ), budget, id_bytes );
+      let mut expected = Vec::new();
+      for _ in 0..30 {
+        let neighbor = grid_neighbor( &mut rng );
+        queue.insert( neighbor );
+
+        expected.push( neighbor );
+        expected.sort_by( neighbor_cmp );
+        expected.dedup_by( |a, b| neighbor_cmp( a, b ) == Ordering::Equal );
+        expected.truncate( capacity );
+        while expected.iter().map( id_bytes ).sum::<usize>() > budget {
+          expected.pop();
+        }
+
+        assert_eq!( queue.as_slice(), expected );
+        assert_eq!( queue.bytes(), expected.iter().map( id_bytes ).sum::<usize>() );
+      }
+    }
+  }
+
+  #[test]
+  fn worst_n_are_the_farthest_in_sorted_order() {
+    let mut queue = queue_of( 8, &[ (5, 0.5), (1, 3.0), (4, 2.0), (2, 1.0), (3, 2.5) ] );
+    assert_eq!( ids( queue.worst_n( 2 ) ), [ 3, 1 ] );
+    assert_eq!( ids( queue.worst_n( 0 ) ), [] );
+    // more than the number of neighbors is all of them
+    assert_eq!( ids( queue.worst_n( 10 ) ), [ 5, 2, 4, 3, 1 ] );
+
+    let drained = queue.drain_worst_n( 2 );
+    assert_eq!( ids( &drained ), [ 3, 1 ] );
+    assert_eq!( ids( queue.as_slice() ), [ 5, 2, 4 ] );
+
+    // the queue takes fresh candidates in their place
+    queue.insert( Neighbor::new( 6, 0.75 ) );
+    assert_eq!( ids( queue.as_slice() ), [ 5, 6, 2, 4 ] );
+
+    let drained = queue.drain_worst_n( 10 );
+    assert_eq!( ids( &drained ), [ 5, 6, 2, 4 ] );
+    assert!( queue.is_empty() );
+    assert!( queue.drain_worst_n( 3 ).is_empty() );
   }
 }
@@ -0,0 +1,73 @@
+use std::cmp::Ordering;
+use std::num::NonZeroUsize;
+use crate::queue::{neighbor_cmp, Neighbor, Queue};
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// known-correct baseline for differential testing: every insert pushes, sorts and truncates
+pub struct ReferenceQueue {
+  neighbors: Vec<Neighbor>,
+  capacity: NonZeroUsize,
+}
+
+impl ReferenceQueue {
+  pub fn with_capacity( capacity: NonZeroUsize ) -> Self {
+    Self { neighbors: Vec::new(), capacity }
+  }
+
+  pub fn as_slice( &self ) -> &[Neighbor] {
+    &self.neighbors
+  }
+
+  pub fn insert( &mut self, neighbor: Neighbor ) {
+    if self.neighbors.iter().any( |other| neighbor_cmp( other, &neighbor ) == Ordering::Equal ) {
+      return;
+    }
+    self.neighbors.push( neighbor );
+    self.neighbors.sort_by( neighbor_cmp );
+    self.neighbors.truncate( self.capacity.get() );
+  }
+
+  // model of `Queue::set_capacity`: lowering the capacity drops the farthest neighbors
+  pub fn set_capacity( &mut self, capacity: NonZeroUsize ) {
+    self.neighbors.truncate( capacity.get() );
+    self.capacity = capacity;
+  }
+
+  pub fn clear( &mut self ) {
+    self.neighbors.clear();
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+pub fn assert_eq_reference( queue: &Queue, reference: &ReferenceQueue ) {
+  let actual = queue.as_slice().iter().map( |n| (n.id, n.dist.to_bits()) ).collect::<Vec<_>>();
+  let expected = reference.as_slice().iter().map( |n| (n.id, n.dist.to_bits()) ).collect::<Vec<_>>();
+  assert_eq!( actual, expected, "queue diverged from the reference" );
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+  use super::*;
+  use rand::Rng;
+  use crate::test_util::{grid_neighbor, rng};
+
+  #[test]
+  fn queue_matches_the_reference() {
+    let mut rng = rng();
+    for _ in 0..200 {
+      let capacity = NonZeroUsize::new( rng.random_range( 1..16 ) ).unwrap();
+      let mut queue = Queue::with_capacity( capacity );
+      let mut reference = ReferenceQueue::with_capacity( capacity );
+      for _ in 0..50 {
+        let neighbor = grid_neighbor( &mut rng );
+        queue.insert( neighbor );
+        reference.insert( neighbor );
+        assert_eq_reference( &queue, &reference );
+      }
+    }
+  }
+}
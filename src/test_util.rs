@@ -0,0 +1,31 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use crate::queue::Neighbor;
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// helpers shared by the test modules
+
+// ids and distance bits, so that -0.0 and NaN compare as they are stored
+pub fn bits( neighbors: &[Neighbor] ) -> Vec<(u32, u32)> {
+  neighbors.iter().map( |neighbor| (neighbor.id, neighbor.dist.to_bits()) ).collect()
+}
+
+// the generator of the randomized tests, always with the same seed so that a failure reproduces
+pub fn rng() -> StdRng {
+  StdRng::seed_from_u64( 0x5eed )
+}
+
+// a small grid of ids and distances, so that ties on the distance and exact duplicates are frequent
+pub fn grid_neighbor( rng: &mut StdRng ) -> Neighbor {
+  Neighbor::new( rng.random_range( 0..16 ), rng.random_range( 0..8 ) as f32 / 4.0 )
+}
+
+// `grid_neighbor` that gets one of `specials` as its distance one time in eight, e.g. NaN, -0.0 or the infinities
+pub fn grid_neighbor_with( rng: &mut StdRng, specials: &[f32] ) -> Neighbor {
+  let neighbor = grid_neighbor( rng );
+  if rng.random_ratio( 1, 8 ) {
+    Neighbor::new( neighbor.id, specials[rng.random_range( 0..specials.len() )] )
+  } else {
+    neighbor
+  }
+}
@@ -0,0 +1,48 @@
+use crate::queue::Neighbor;
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// deterministic dataset shared by the bench, the example and downstream benchmarks: ids `0..count` in a shuffled order,
+// with distances uniform in [0, 1)
+pub fn gen_neighbors( count: usize, seed: [u8; 32] ) -> Vec<Neighbor> {
+  use rand::{
+    SeedableRng,
+    distr::{Distribution, Uniform},
+    rngs::StdRng,
+    seq::SliceRandom,
+  };
+
+  let mut rng = StdRng::from_seed( seed );
+  let range = Uniform::new( 0.0f32, 1.0f32 ).unwrap();
+
+  let mut neighbors = Vec::with_capacity( count );
+  let mut ids = (0..count as u32).collect::<Vec<u32>>();
+  ids.shuffle( &mut rng );
+
+  for id in ids {
+    let dist = range.sample( &mut rng );
+    neighbors.push( Neighbor::new( id, dist ) );
+  }
+
+  neighbors
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+  use super::*;
+  use crate::test_util::bits;
+
+  #[test]
+  fn same_seed_same_neighbors() {
+    let neighbors = gen_neighbors( 1_000, [ 42u8; 32 ] );
+    assert_eq!( bits( &neighbors ), bits( &gen_neighbors( 1_000, [ 42u8; 32 ] ) ) );
+    assert_ne!( bits( &neighbors ), bits( &gen_neighbors( 1_000, [ 7u8; 32 ] ) ) );
+
+    let mut ids = neighbors.iter().map( |neighbor| neighbor.id ).collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!( ids, (0..1_000).collect::<Vec<_>>() );
+    assert!( neighbors.iter().all( |neighbor| (0.0..1.0).contains( &neighbor.dist ) ) );
+  }
+}
@@ -0,0 +1,133 @@
+use std::cmp::Ordering;
+use std::num::NonZeroUsize;
+use crate::queue::Neighbor;
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// neighbor carrying an application priority that breaks distance ties before the id
+#[derive(Debug, Clone, Copy)]
+pub struct TieredNeighbor {
+  pub id: u32,
+  pub dist: f32,
+  pub tiebreak: u32,
+}
+
+impl TieredNeighbor {
+  pub const fn new( id: u32, dist: f32, tiebreak: u32 ) -> Self {
+    Self { id, dist, tiebreak }
+  }
+}
+
+// a tiebreak of 0 ranks the neighbor as `Queue` does
+impl From<Neighbor> for TieredNeighbor {
+  fn from( neighbor: Neighbor ) -> Self {
+    Self::new( neighbor.id, neighbor.dist, 0 )
+  }
+}
+
+// the total order of the tiered queue: by distance, then by tiebreak, then by id
+#[inline(always)]
+pub fn tiered_cmp( a: &TieredNeighbor, b: &TieredNeighbor ) -> Ordering {
+  if a.dist < b.dist { Ordering::Less }
+  else if a.dist == b.dist { (a.tiebreak, a.id).cmp( &(b.tiebreak, b.id) ) }
+  else { Ordering::Greater }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// same queue as `Queue` with a secondary sort key. It is a type of its own so that `Neighbor` and the search of
+// `Queue::insert` are left untouched.
+pub struct TieredQueue {
+  neighbors: Vec<TieredNeighbor>,
+  capacity: NonZeroUsize,
+}
+
+impl TieredQueue {
+  pub fn with_capacity( capacity: NonZeroUsize ) -> Self {
+    let neighbors = Vec::with_capacity( capacity.get() );
+    Self { neighbors, capacity }
+  }
+
+  pub fn as_slice( &self ) -> &[TieredNeighbor] {
+    &self.neighbors
+  }
+
+  pub fn len( &self ) -> usize {
+    self.neighbors.len()
+  }
+
+  pub fn is_empty( &self ) -> bool {
+    self.neighbors.is_empty()
+  }
+
+  pub fn capacity( &self ) -> NonZeroUsize {
+    self.capacity
+  }
+}
+
+impl TieredQueue {
+  #[inline(never)]
+  pub fn insert( &mut self, neighbor: TieredNeighbor ) {
+    if let Err( pos ) = self.neighbors.binary_search_by( |other| tiered_cmp( other, &neighbor ) ) && pos < self.capacity.get() {
+      if self.neighbors.len() == self.capacity.get() {
+        _ = self.neighbors.pop();
+      }
+      unsafe { std::hint::assert_unchecked( self.neighbors.len() < self.neighbors.capacity() ) };
+      self.neighbors.insert( pos, neighbor );
+    }
+  }
+
+  pub fn clear( &mut self ) {
+    self.neighbors.clear();
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::queue::Queue;
+
+  fn ids( queue: &TieredQueue ) -> Vec<u32> {
+    queue.as_slice().iter().map( |neighbor| neighbor.id ).collect()
+  }
+
+  #[test]
+  fn tiebreak_ranks_equal_distances_before_the_id() {
+    let mut queue = TieredQueue::with_capacity( NonZeroUsize::new( 8 ).unwrap() );
+    queue.insert( TieredNeighbor::new( 1, 0.5, 2 ) );
+    queue.insert( TieredNeighbor::new( 2, 0.5, 0 ) );
+    queue.insert( TieredNeighbor::new( 3, 0.5, 1 ) );
+    queue.insert( TieredNeighbor::new( 4, 0.5, 1 ) );
+    queue.insert( TieredNeighbor::new( 5, 0.25, 9 ) );
+    assert_eq!( ids( &queue ), [ 5, 2, 3, 4, 1 ] );
+  }
+
+  #[test]
+  fn tiebreak_decides_the_eviction_on_equal_distances() {
+    let mut queue = TieredQueue::with_capacity( NonZeroUsize::new( 2 ).unwrap() );
+    queue.insert( TieredNeighbor::new( 1, 0.5, 3 ) );
+    queue.insert( TieredNeighbor::new( 2, 0.5, 2 ) );
+    queue.insert( TieredNeighbor::new( 3, 0.5, 1 ) );
+    assert_eq!( ids( &queue ), [ 3, 2 ] );
+    queue.insert( TieredNeighbor::new( 4, 0.5, 5 ) );
+    assert_eq!( ids( &queue ), [ 3, 2 ] );
+  }
+
+  #[test]
+  fn zero_tiebreak_matches_queue() {
+    let capacity = NonZeroUsize::new( 16 ).unwrap();
+    let mut queue = Queue::with_capacity( capacity );
+    let mut tiered = TieredQueue::with_capacity( capacity );
+    for i in 0..200u32 {
+      // coarse distances so that ties are frequent
+      let neighbor = Neighbor::new( i.wrapping_mul( 2_654_435_761 ) % 64, (i % 7) as f32 );
+      queue.insert( neighbor );
+      tiered.insert( neighbor.into() );
+    }
+    let expected = queue.as_slice().iter().map( |neighbor| (neighbor.id, neighbor.dist) ).collect::<Vec<_>>();
+    let actual = tiered.as_slice().iter().map( |neighbor| (neighbor.id, neighbor.dist) ).collect::<Vec<_>>();
+    assert_eq!( actual, expected );
+  }
+}
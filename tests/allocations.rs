@@ -0,0 +1,129 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::num::NonZeroUsize;
+use test_pqueue::queue::{Growth, Neighbor, Queue};
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+// counts the allocations and reallocations of each thread, so that the tests running in parallel do not see each other's
+struct Counting;
+
+thread_local! {
+  static ALLOCATIONS: Cell<usize> = const { Cell::new( 0 ) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+  unsafe fn alloc( &self, layout: Layout ) -> *mut u8 {
+    ALLOCATIONS.with( |count| count.set( count.get() + 1 ) );
+    unsafe { System.alloc( layout ) }
+  }
+
+  unsafe fn dealloc( &self, ptr: *mut u8, layout: Layout ) {
+    unsafe { System.dealloc( ptr, layout ) }
+  }
+
+  unsafe fn realloc( &self, ptr: *mut u8, layout: Layout, new_size: usize ) -> *mut u8 {
+    ALLOCATIONS.with( |count| count.set( count.get() + 1 ) );
+    unsafe { System.realloc( ptr, layout, new_size ) }
+  }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+// allocations and reallocations made by `f` on this thread
+fn allocations<T>( f: impl FnOnce() -> T ) -> (T, usize) {
+  let before = ALLOCATIONS.with( Cell::get );
+  let result = f();
+  (result, ALLOCATIONS.with( Cell::get ) - before)
+}
+
+fn nz( n: usize ) -> NonZeroUsize {
+  NonZeroUsize::new( n ).unwrap()
+}
+
+fn fill( queue: &mut Queue, count: u32 ) {
+  for id in 0..count {
+    queue.insert( Neighbor::new( id, id as f32 ) );
+  }
+}
+
+// ---------------------------------------------------------------------------------------------------------------------------------
+
+#[test]
+fn set_capacity_keeps_the_high_water_mark() {
+  let (queue, count) = allocations( || {
+    let mut queue = Queue::with_capacity( nz( 10 ) );
+    for capacity in [ 200, 10, 200, 10, 200 ] {
+      queue.set_capacity( nz( capacity ) );
+      fill( &mut queue, 300 );
+      assert_eq!( queue.len(), capacity );
+    }
+    queue
+  });
+  assert_eq!( count, 2 );
+  assert_eq!( queue.allocated_capacity(), 200 );
+}
+
+#[test]
+fn reset_only_allocates_past_the_allocated_capacity() {
+  let mut queue = Queue::with_capacity( nz( 10 ) );
+  fill( &mut queue, 20 );
+
+  let ((), count) = allocations( || {
+    queue.reset( nz( 10 ) );
+    fill( &mut queue, 20 );
+  });
+  assert_eq!( count, 0 );
+  assert_eq!( queue.len(), 10 );
+
+  let ((), count) = allocations( || queue.reset( nz( 100 ) ) );
+  assert_eq!( count, 1 );
+  assert!( queue.is_empty() );
+  assert_eq!( queue.capacity(), nz( 100 ) );
+
+  let ((), count) = allocations( || {
+    queue.reset( nz( 10 ) );
+    queue.reset( nz( 100 ) );
+    fill( &mut queue, 200 );
+  });
+  assert_eq!( count, 0 );
+  assert_eq!( queue.len(), 100 );
+}
+
+#[test]
+fn unbounded_grows_following_the_strategy() {
+  for (growth, expected, allocated) in [
+    (Growth::Doubling, 9, 1024),
+    (Growth::Fixed( nz( 100 ) ), 10, 1000),
+    (Growth::Exact, 1000, 1000),
+  ] {
+    let (queue, count) = allocations( || {
+      let mut queue = Queue::unbounded( growth );
+      fill( &mut queue, 1000 );
+      queue
+    });
+    assert_eq!( count, expected, "{growth:?}" );
+    assert_eq!( queue.len(), 1000 );
+    assert_eq!( queue.allocated_capacity(), allocated, "{growth:?}" );
+  }
+}
+
+#[test]
+fn extend_reserves_once_from_the_size_hint() {
+  let neighbors = (0..10_000).map( |id| Neighbor::new( id, (id % 97) as f32 ) ).collect::<Vec<_>>();
+
+  let (queue, count) = allocations( || {
+    let mut queue = Queue::unbounded( Growth::Doubling );
+    queue.extend( neighbors.iter().copied() );
+    queue
+  });
+  assert!( count <= 2, "{count} allocations" );
+  assert_eq!( queue.len(), 10_000 );
+
+  // a bounded queue does not reserve past its capacity
+  let mut queue = Queue::with_capacity( nz( 10 ) );
+  let ((), count) = allocations( || queue.extend( neighbors.iter().copied() ) );
+  assert_eq!( count, 0 );
+  assert_eq!( queue.allocated_capacity(), 10 );
+}